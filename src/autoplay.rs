@@ -0,0 +1,249 @@
+use crate::engine::engine::{self, Event, Game};
+use crate::playfield::{Playfield, FieldTetrimino, Coords, Dir, ClearMode};
+use crate::figures::figures::{Shape, Tetrimino, Orientation, rotate};
+use crate::rotation::{RotationSystem};
+
+/* Weights of board features, positive ones are good. Defaults are
+ * the well known ones found by genetic search for guideline Tetris. */
+#[derive(Clone, Copy, Debug)]
+pub struct Weights {
+    pub aggregate_height: f32,
+    pub lines: f32,
+    pub holes: f32,
+    pub bumpiness: f32,
+    pub wells: f32,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights{aggregate_height: -0.51, lines: 0.76, holes: -0.36, bumpiness: -0.18, wells: -0.1}
+    }
+}
+
+/* Where the player wants the active piece */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Placement {
+    pub hold: bool,
+    pub orientation: Orientation,
+    /* column of layout top left corner */
+    pub col: i8,
+}
+
+const ORIENTATIONS: [Orientation; 4] = [Orientation::Spawn, Orientation::Right, Orientation::Reverse, Orientation::Left];
+
+fn filled(playfield: &Playfield, row: i8, col: i8) -> bool {
+    *playfield.storage().cell(&Coords{row: row, col: col}) != Shape::NoShape
+}
+
+/* Weighted sum of board features after some lines were cleared */
+pub fn evaluate(playfield: &Playfield, lines: u32, weights: &Weights) -> f32 {
+    let dimensions = playfield.dimensions();
+    let heights: Vec<i8> = (0..dimensions.width).map(|col| {
+        (0..dimensions.total_height()).rev().find(|row| filled(playfield, *row, col)).map_or(0, |row| row + 1)
+    }).collect();
+
+    let holes = (0..dimensions.width).map(|col| {
+        (0..heights[col as usize]).filter(|row| !filled(playfield, *row, col)).count()
+    }).sum::<usize>();
+    let bumpiness = heights.windows(2).map(|w| (w[0] - w[1]).abs() as i32).sum::<i32>();
+    /* walls are as high as the playfield */
+    let wells = (0..heights.len()).map(|col| {
+        let left = if col == 0 { dimensions.total_height() } else { heights[col - 1] };
+        let right = heights.get(col + 1).cloned().unwrap_or(dimensions.total_height());
+        std::cmp::max(std::cmp::min(left, right) - heights[col], 0) as i32
+    }).sum::<i32>();
+    let aggregate_height = heights.iter().map(|h| *h as i32).sum::<i32>();
+
+    weights.aggregate_height * aggregate_height as f32 + weights.lines * lines as f32
+        + weights.holes * holes as f32 + weights.bumpiness * bumpiness as f32 + weights.wells * wells as f32
+}
+
+/* Hard drop shape in orientation from the top of given column. Returns playfield
+ * with lines cleared the way the game clears them and their number, None if shape doesn't fit. */
+fn drop_piece(playfield: &Playfield, shape: &Shape, orientation: Orientation, col: i8,
+              rotation_system: &'static dyn RotationSystem, clear_mode: ClearMode) -> Option<(Playfield, u32)> {
+    let mut tetro = Tetrimino::with_system(shape.clone(), rotation_system);
+    for _ in 0..ORIENTATIONS.len() {
+        if tetro.orientation() == orientation {
+            break;
+        }
+        rotate(&mut tetro);
+    }
+    let dimensions = playfield.dimensions();
+    let mut piece = FieldTetrimino{coords: Coords{row: dimensions.height - 1 + tetro.bottom_row(), col: col}, tetro: tetro};
+    if !playfield.can_place(&piece.tetro, &piece.coords) {
+        return None;
+    }
+    while playfield.move_tetro(&mut piece, Dir::Down) {}
+
+    let mut playfield = playfield.clone();
+    let _ = playfield.place(&piece.tetro, piece.coords);
+    let lines = playfield.clear_filled(clear_mode);
+    Some((playfield, lines))
+}
+
+/* Every distinct landing of a shape, orientations that look the same are tried anyway */
+fn placements(playfield: &Playfield, shape: &Shape, rotation_system: &'static dyn RotationSystem, clear_mode: ClearMode)
+              -> Vec<(Orientation, i8, Playfield, u32)> {
+    let width = playfield.dimensions().width;
+    let mut result = Vec::new();
+    for orientation in ORIENTATIONS.iter() {
+        /* layout may stick out to the left of an empty column */
+        for col in -2..width {
+            if let Some((after, lines)) = drop_piece(playfield, shape, *orientation, col, rotation_system, clear_mode) {
+                result.push((*orientation, col, after, lines));
+            }
+        }
+    }
+    result
+}
+
+/* Best placement for the active piece, looking one preview piece ahead */
+pub fn choose(game: &Game, weights: &Weights) -> Option<Placement> {
+    let active = engine::active(game)?;
+    let (rotation_system, clear_mode) = (engine::config(game).rotation_system, engine::config(game).clear_mode);
+    let playfield = engine::playfield(game);
+    let next = engine::next_queue(game);
+
+    /* hold swaps active piece with the first one in the queue */
+    let mut candidates = vec![(false, active.tetro.shape.clone(), next.first().cloned())];
+    if engine::hold(game).is_none() {
+        if let Some(first) = next.first() {
+            candidates.push((true, first.clone(), Some(active.tetro.shape.clone())));
+        }
+    }
+
+    let mut best: Option<(f32, Placement)> = None;
+    for (hold, shape, lookahead) in candidates {
+        for (orientation, col, after, lines) in placements(playfield, &shape, rotation_system, clear_mode) {
+            let score = match &lookahead {
+                Some(lookahead) => placements(&after, lookahead, rotation_system, clear_mode).iter()
+                    .map(|(_, _, last, more_lines)| evaluate(last, lines + more_lines, weights))
+                    .fold(None, |best: Option<f32>, score| Some(best.map_or(score, |b| b.max(score))))
+                    .unwrap_or_else(|| evaluate(&after, lines, weights)),
+                None => evaluate(&after, lines, weights),
+            };
+            if best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, Placement{hold: hold, orientation: orientation, col: col}));
+            }
+        }
+    }
+    best.map(|(_, placement)| placement)
+}
+
+/* Plays through the normal input path, one event at a time */
+pub struct AutoPlayer {
+    weights: Weights,
+    /* piece number the plan is made for */
+    piece: u32,
+    plan: Option<Placement>,
+    held: bool,
+    turns: usize,
+    last_col: Option<i8>,
+    dropped: bool,
+}
+
+impl AutoPlayer {
+    pub fn new(weights: Weights) -> Self {
+        AutoPlayer{weights: weights, piece: 0, plan: None, held: false, turns: 0, last_col: None, dropped: false}
+    }
+
+    /* Next input towards chosen placement, None if there is nothing to do right now */
+    pub fn next_event(self: &mut Self, game: &Game) -> Option<Event> {
+        if engine::is_finished(game) || engine::is_paused(game) {
+            return None;
+        }
+        let active = engine::active(game)?;
+        if engine::pieces(game) != self.piece {
+            self.piece = engine::pieces(game);
+            self.plan = choose(game, &self.weights);
+            self.held = false;
+            self.turns = 0;
+            self.last_col = None;
+            self.dropped = false;
+        }
+        /* piece waits for lock delay after drop */
+        if self.dropped {
+            return None;
+        }
+        let plan = self.plan?;
+
+        if plan.hold && !self.held {
+            self.held = true;
+            return Some(Event::KeyHold);
+        }
+        if active.tetro.orientation() != plan.orientation && self.turns < ORIENTATIONS.len() {
+            self.turns += 1;
+            return Some(Event::KeyTurn);
+        }
+        /* stop moving once the piece is stuck */
+        let col = active.coords.col;
+        if col != plan.col && self.last_col != Some(col) {
+            self.last_col = Some(col);
+            return Some(if col < plan.col { Event::KeyRight } else { Event::KeyLeft });
+        }
+        self.dropped = true;
+        Some(Event::KeyDrop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::engine::{Config, Mode};
+    use crate::rotation::{SRS};
+    use crate::board::{Board};
+
+    #[test]
+    fn board_features() {
+        let weights = Weights{aggregate_height: 1.0, lines: 0.0, holes: 0.0, bumpiness: 0.0, wells: 0.0};
+        let board = Board::parse("size 4 4\n....\n.G..\nG...\nGG.G\n", &SRS).unwrap();
+        let playfield = Playfield::new(board.storage);
+        /* heights 2 3 0 1 */
+        assert_eq!(evaluate(&playfield, 0, &weights), 6.0);
+        let holes = Weights{aggregate_height: 0.0, holes: 1.0, ..weights};
+        assert_eq!(evaluate(&playfield, 0, &holes), 1.0);
+        let bumpiness = Weights{aggregate_height: 0.0, bumpiness: 1.0, ..weights};
+        assert_eq!(evaluate(&playfield, 0, &bumpiness), 1.0 + 3.0 + 1.0);
+        /* column 0 sits between left wall and height 3, column 2 between heights 3 and 1 */
+        let wells = Weights{aggregate_height: 0.0, wells: 1.0, ..weights};
+        assert_eq!(evaluate(&playfield, 0, &wells), 1.0 + 1.0);
+    }
+
+    #[test]
+    fn fills_the_gap() {
+        /* only a vertical I fits into the well without holes */
+        let board = Board::parse("size 4 6\n....\n....\nGGG.\nGGG.\nGGG.\nGGG.\n", &SRS).unwrap();
+        let playfield = Playfield::new(board.storage);
+        let best = placements(&playfield, &Shape::IShape, &SRS, ClearMode::Naive).into_iter()
+            .max_by(|a, b| evaluate(&a.2, a.3, &Weights::default()).partial_cmp(&evaluate(&b.2, b.3, &Weights::default())).unwrap())
+            .unwrap();
+        assert_eq!(best.3, 4);
+    }
+
+    #[test]
+    fn cascade_chains_count() {
+        /* filling the right column clears a row, the o cell then falls into the hole below */
+        let board = Board::parse("size 4 6\n....\n....\n....\n.O..\nGGG.\nG.GG\n", &SRS).unwrap();
+        let playfield = Playfield::new(board.storage);
+        let most_lines = |clear_mode| placements(&playfield, &Shape::IShape, &SRS, clear_mode).iter().map(|p| p.3).max();
+        assert_eq!(most_lines(ClearMode::Naive), Some(1));
+        assert_eq!(most_lines(ClearMode::Cascade), Some(2));
+    }
+
+    #[test]
+    fn plays_a_long_game() {
+        let config = Config{no_ghost: false, level: 0, next_queue_size: 1, mode: Mode::Marathon, rotation_system: &SRS,
+                            dimensions: Default::default(), clear_mode: ClearMode::Naive, seed: 9};
+        let mut game = engine::new_game(config.clone()).unwrap();
+        let mut player = AutoPlayer::new(Weights::default());
+        while engine::pieces(&game) < 50 && !engine::is_finished(&game) {
+            engine::calculate_frame(&mut game, Event::Timeout);
+            if let Some(event) = player.next_event(&game) {
+                engine::calculate_frame(&mut game, event);
+            }
+        }
+        assert_eq!(engine::is_finished(&game), false);
+        assert_eq!(engine::lines(&game) >= 15, true);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::figures::figures::{Shape, Tetrimino, Orientation, Rotation, turn};
+use crate::playfield::{Storage, Dimensions, FieldTetrimino, Coords};
+use crate::rotation::{RotationSystem};
+use crate::piece_set::{ParseError};
+
+/* Board text format:
+ *
+ *   ; comment
+ *   size 10 20
+ *   active T 4 21 right
+ *   hold I
+ *   next SZLO
+ *   ..........
+ *   ...T......
+ *   G.GGGGGGGG
+ *
+ * Header lines are optional and come before the grid. Size is playfield
+ * width and visible height, guideline 10x20 is used without it. Active
+ * piece is given by its letter, column and row of its layout top left
+ * corner and orientation (spawn, right, reverse or left). Grid rows go
+ * from top to bottom, the last one is playfield row 0 and rows above the
+ * grid stay empty. Every cell is a piece letter, G for garbage or '.'. */
+
+pub struct Board {
+    pub storage: Storage,
+    pub active: Option<FieldTetrimino>,
+    pub hold: Option<Shape>,
+    pub next: Vec<Shape>,
+}
+
+const ORIENTATIONS: [(Orientation, &str); 4] = [
+    (Orientation::Spawn, "spawn"), (Orientation::Right, "right"),
+    (Orientation::Reverse, "reverse"), (Orientation::Left, "left"),
+];
+
+fn orientation_name(orientation: Orientation) -> &'static str {
+    ORIENTATIONS.iter().find(|(o, _)| *o == orientation).unwrap().1
+}
+
+/* Garbage letter is reserved, so it wins over custom pieces with the same name */
+pub fn shape_by_name(name: char, rotation_system: &dyn RotationSystem) -> Option<Shape> {
+    match name {
+        '.' => Some(Shape::NoShape),
+        'G' => Some(Shape::Garbage),
+        _ => rotation_system.shapes().into_iter().find(|s| s.name() == name),
+    }
+}
+
+fn parse_shapes(line_no: usize, names: &str, rotation_system: &dyn RotationSystem) -> Result<Vec<Shape>, ParseError> {
+    names.chars().map(|c| match shape_by_name(c, rotation_system) {
+        Some(Shape::NoShape) | Some(Shape::Garbage) | None =>
+            Err(ParseError{line: line_no, reason: format!("unknown piece {}", c)}),
+        Some(shape) => Ok(shape),
+    }).collect()
+}
+
+fn parse_number(line_no: usize, field: &str) -> Result<i8, ParseError> {
+    field.parse::<i8>().map_err(|_| ParseError{line: line_no, reason: format!("bad number {}", field)})
+}
+
+fn parse_active(line_no: usize, fields: &[&str], rotation_system: &'static dyn RotationSystem) -> Result<FieldTetrimino, ParseError> {
+    if fields.len() != 4 {
+        return Err(ParseError{line: line_no, reason: "expected: active <letter> <col> <row> <orientation>".to_string()});
+    }
+    let shapes = parse_shapes(line_no, fields[0], rotation_system)?;
+    if shapes.len() != 1 {
+        return Err(ParseError{line: line_no, reason: format!("bad active piece {}", fields[0])});
+    }
+    let orientation = match ORIENTATIONS.iter().find(|(_, name)| *name == fields[3]) {
+        Some((orientation, _)) => *orientation,
+        None => return Err(ParseError{line: line_no, reason: format!("bad orientation {}", fields[3])}),
+    };
+    let mut tetro = Tetrimino::with_system(shapes[0].clone(), rotation_system);
+    while tetro.orientation() != orientation {
+        turn(&mut tetro, Rotation::Clockwise);
+    }
+    Ok(FieldTetrimino{
+        tetro: tetro,
+        coords: Coords{col: parse_number(line_no, fields[1])?, row: parse_number(line_no, fields[2])?},
+    })
+}
+
+impl Board {
+    pub fn new(storage: Storage) -> Board {
+        Board{storage: storage, active: None, hold: None, next: Vec::new()}
+    }
+
+    pub fn parse(text: &str, rotation_system: &'static dyn RotationSystem) -> Result<Board, ParseError> {
+        let mut dimensions = Dimensions::default();
+        let mut active = None;
+        let mut hold = None;
+        let mut next = Vec::new();
+        let mut grid: Vec<(usize, &str)> = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line_no = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            /* grid rows never contain spaces, header lines always do */
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() == 1 {
+                grid.push((line_no, line));
+                continue;
+            }
+            if !grid.is_empty() {
+                return Err(ParseError{line: line_no, reason: "header after grid".to_string()});
+            }
+            match fields[0] {
+                "size" if fields.len() == 3 => {
+                    dimensions = Dimensions::new(parse_number(line_no, fields[1])?, parse_number(line_no, fields[2])?);
+                    if !dimensions.is_valid() {
+                        return Err(ParseError{line: line_no, reason: "unsupported size".to_string()});
+                    }
+                },
+                "active" => active = Some(parse_active(line_no, &fields[1..], rotation_system)?),
+                "hold" if fields.len() == 2 => {
+                    hold = parse_shapes(line_no, fields[1], rotation_system)?.pop();
+                },
+                "next" if fields.len() == 2 => next = parse_shapes(line_no, fields[1], rotation_system)?,
+                _ => return Err(ParseError{line: line_no, reason: format!("bad header: {}", line)}),
+            }
+        }
+
+        if grid.len() > dimensions.total_height() as usize {
+            return Err(ParseError{line: grid[0].0, reason: "too many rows".to_string()});
+        }
+        let mut storage = Storage::new(dimensions);
+        for (row, (line_no, line)) in grid.iter().rev().enumerate() {
+            if line.chars().count() != dimensions.width as usize {
+                return Err(ParseError{line: *line_no, reason: format!("expected {} cells", dimensions.width)});
+            }
+            for (col, c) in line.chars().enumerate() {
+                match shape_by_name(c, rotation_system) {
+                    Some(shape) => storage.set_cell(&Coords{row: row as i8, col: col as i8}, shape),
+                    None => return Err(ParseError{line: *line_no, reason: format!("unknown piece {}", c)}),
+                }
+            }
+        }
+
+        Ok(Board{storage: storage, active: active, hold: hold, next: next})
+    }
+
+    pub fn load(path: &str, rotation_system: &'static dyn RotationSystem) -> Result<Board, ParseError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Board::parse(&text, rotation_system),
+            Err(e) => Err(ParseError{line: 0, reason: e.to_string()}),
+        }
+    }
+
+    /* Grid always covers visible area and anything stacked above it */
+    pub fn to_text(self: &Self) -> String {
+        let mut text = String::new();
+        let dimensions = self.storage.dimensions();
+        if dimensions != Dimensions::default() {
+            text += &format!("size {} {}\n", dimensions.width, dimensions.height);
+        }
+        if let Some(active) = &self.active {
+            text += &format!("active {} {} {} {}\n", active.tetro.shape.name(), active.coords.col, active.coords.row,
+                             orientation_name(active.tetro.orientation()));
+        }
+        if let Some(hold) = &self.hold {
+            text += &format!("hold {}\n", hold.name());
+        }
+        if !self.next.is_empty() {
+            text += &format!("next {}\n", self.next.iter().map(|s| s.name()).collect::<String>());
+        }
+
+        let top = (dimensions.height..dimensions.total_height()).rev()
+            .find(|row| (0..dimensions.width).any(|col| *self.storage.cell(&Coords{row: *row, col: col}) != Shape::NoShape))
+            .map_or(dimensions.height, |row| row + 1);
+        for row in (0..top).rev() {
+            for col in 0..dimensions.width {
+                text.push(self.storage.cell(&Coords{row: row, col: col}).name());
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rotation::{SRS};
+    use crate::piece_set::{PieceSet};
+    use crate::playfield::{Playfield};
+
+    const PUZZLE: &str = "; t-spin double
+size 6 4
+active T 1 2 reverse
+hold I
+next SZ
+......
+OO....
+O...LL
+OO.LLL
+";
+
+    fn error_line(text: &str) -> usize {
+        match Board::parse(text, &SRS) {
+            Err(e) => e.line,
+            Ok(_) => panic!("parsed invalid board: {:?}", text),
+        }
+    }
+
+    #[test]
+    fn parse_header_and_grid() {
+        let board = Board::parse(PUZZLE, &SRS).unwrap();
+        assert_eq!(board.storage.dimensions(), Dimensions::new(6, 4));
+        assert_eq!(*board.storage.cell(&Coords{row: 0, col: 0}), Shape::OShape);
+        assert_eq!(*board.storage.cell(&Coords{row: 0, col: 2}), Shape::NoShape);
+        assert_eq!(*board.storage.cell(&Coords{row: 1, col: 5}), Shape::LShape);
+        assert_eq!(*board.storage.cell(&Coords{row: 3, col: 0}), Shape::NoShape);
+        assert_eq!(board.hold, Some(Shape::IShape));
+        assert_eq!(board.next, vec![Shape::SShape, Shape::ZShape]);
+
+        let active = board.active.unwrap();
+        assert_eq!(active.tetro.shape, Shape::TShape);
+        assert_eq!(active.tetro.orientation(), Orientation::Reverse);
+        /* piece fits into its slot */
+        let playfield = Playfield::new(board.storage);
+        assert_eq!(playfield.can_place(&active.tetro, &active.coords), true);
+        assert_eq!(playfield.has_fall_space(&active), false);
+    }
+
+    #[test]
+    fn board_round_trip() {
+        let board = Board::parse(PUZZLE, &SRS).unwrap();
+        let text = board.to_text();
+        assert_eq!(text, PUZZLE.replacen("; t-spin double\n", "", 1));
+        assert_eq!(Board::parse(&text, &SRS).unwrap().to_text(), text);
+    }
+
+    #[test]
+    fn default_size_and_buffer_rows() {
+        let mut text = String::new();
+        for _ in 0..21 {
+            text += "G.........\n";
+        }
+        let board = Board::parse(&text, &SRS).unwrap();
+        assert_eq!(board.storage.dimensions(), Dimensions::default());
+        assert_eq!(*board.storage.cell(&Coords{row: 20, col: 0}), Shape::Garbage);
+        /* empty board still lists every visible row */
+        let empty = Board::new(Storage::default()).to_text();
+        assert_eq!(empty.lines().count(), 20);
+        assert_eq!(board.to_text(), text);
+    }
+
+    #[test]
+    fn custom_piece_letters() {
+        let set: &'static PieceSet = Box::leak(Box::new(PieceSet::pentominoes()));
+        let board = Board::parse("size 5 4\nnext Xf\n.....\n..X..\n.XXX.\nffGGG\n", set).unwrap();
+        assert_eq!(board.next.iter().map(|s| s.name()).collect::<String>(), "Xf");
+        assert_eq!(board.storage.cell(&Coords{row: 1, col: 2}).name(), 'X');
+        assert_eq!(board.storage.cell(&Coords{row: 0, col: 2}).name(), 'G');
+        /* tetromino letters mean nothing for pentomino set */
+        assert_eq!(Board::parse("size 5 4\nOO...\n", set).is_err(), true);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(error_line("size 30 20\n"), 1);
+        assert_eq!(error_line("..........\n.........\n"), 2);
+        assert_eq!(error_line("..........\nsize 10 20\n"), 2);
+        assert_eq!(error_line("..........\n....X.....\n"), 2);
+        assert_eq!(error_line("; comment\nactive T 1 2\n"), 2);
+        assert_eq!(error_line("active T 1 2 upside\n"), 1);
+        assert_eq!(error_line("next SZG\n"), 1);
+        assert_eq!(error_line("bogus header\n"), 1);
+    }
+}
@@ -1,15 +1,28 @@
 use crate::updateable_view::{UpdatableView, Ctrl};
 use crate::view::{View, ShowArgs};
-use crate::engine::engine::{Mode};
+use crate::engine::engine::{GameOverReason};
 
+#[derive(Clone)]
 pub struct EndgameCtrl {
     view: UpdatableView,
-    game_over: bool,
+    reason: Option<GameOverReason>,
+    /* zen mode starts over instead of game over */
+    resets: u32,
+    resets_view: UpdatableView,
 }
 
 impl Ctrl for EndgameCtrl {
     fn show(self: &mut Self, view: &mut impl View) {
-        self.view.show(view, &ShowArgs::EndgameArgs{game_over: self.game_over});
+        if let Some(reason) = self.reason {
+            self.view.show(view, &ShowArgs::EndgameArgs{reason: reason});
+        }
+        if self.resets > 0 {
+            self.resets_view.show(view, &ShowArgs::ResetsArgs{resets: self.resets});
+        }
+    }
+    fn redraw(self: &mut Self) {
+        self.view.update();
+        self.resets_view.update();
     }
 }
 
@@ -18,10 +31,38 @@ impl EndgameCtrl {
         self.view.update();
     }
 
-    pub fn new(mode: Mode) -> Self {
+    /* First reason wins, e.g. quitting after top-out doesn't change anything */
+    pub fn finish(self: &mut Self, reason: GameOverReason) {
+        if self.reason.is_none() {
+            self.reason = Some(reason);
+        }
+    }
+
+    pub fn reason(self: &Self) -> Option<GameOverReason> {
+        self.reason
+    }
+
+    pub fn board_reset(self: &mut Self) {
+        self.resets += 1;
+        self.resets_view.update();
+    }
+
+    pub fn resets(self: &Self) -> u32 {
+        self.resets
+    }
+
+    /* Continue saved game */
+    pub fn restore(self: &mut Self, resets: u32) {
+        self.resets = resets;
+        self.resets_view.update();
+    }
+
+    pub fn new() -> Self {
         EndgameCtrl{
             view: UpdatableView::new(false),
-            game_over: mode == Mode::Marathon,
+            reason: None,
+            resets: 0,
+            resets_view: UpdatableView::new(false),
         }
     }
 }
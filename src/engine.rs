@@ -3,22 +3,40 @@ pub mod engine {
     use crate::playfield as playfield;
     use crate::playfield_ctrl::{PlayfieldCtrl};
     use crate::score_ctrl::{ScoreCtrl, Score};
-    use crate::next_tetro_ctrl::{NextTetroCtrl};
+    use crate::next_tetro_ctrl::{self, NextTetroCtrl, BagState};
     use crate::static_ctrl::{StaticCtrl};
     use crate::updateable_view::Ctrl;
-    use crate::fall::{Fall};
+    use crate::fall::{Fall, FRAME_RATE};
     use crate::playtime_ctrl::{PlaytimeCtrl};
     use crate::endgame_ctrl::{EndgameCtrl};
+    use crate::pause_ctrl::{PauseCtrl};
+    use crate::garbage::{Garbage};
+    use crate::master;
+    use crate::figures::figures::{Rotation};
+    use crate::rotation::{RotationSystem};
+    use crate::tlv::tlv;
+    use crate::replay::{self, ReplayError};
+    use crate::board::{self, Board};
+    use crate::piece_set::{ParseError};
+    use crate::figures::figures::{Shape, Orientation};
+    use std::io::{Read, Write};
     use std::fmt;
 
+    #[derive(Clone)]
     pub struct Config {
         pub no_ghost: bool,
         pub level: u8,
         pub next_queue_size: u8,
         pub mode: Mode,
+        pub rotation_system: &'static dyn RotationSystem,
+        /* size of the playfield, new_game makes an empty one */
+        pub dimensions: playfield::Dimensions,
+        pub clear_mode: playfield::ClearMode,
+        /* same seed and same events give the same game */
+        pub seed: u64,
     }
 
-    #[derive(Clone, PartialEq)]
+    #[derive(Clone, PartialEq, Debug)]
     pub enum State {
         GenerationPhase,
         CompletionPhase,
@@ -26,6 +44,8 @@ pub mod engine {
         LockedPhase,
         PatternPhase,
         AnimationPhase,
+        /* nothing moves until player resumes and countdown ends */
+        Paused,
         /* Eliminate Phase is subphase of Completion */
         GameOver,
     }
@@ -40,22 +60,55 @@ pub mod engine {
                 State::LockedPhase => "locked",
                 State::PatternPhase => "pattern",
                 State::AnimationPhase => "animation",
+                State::Paused => "paused",
             };
 
             write!(f, "{}", result)
         }
     }
 
-    #[derive(Clone, PartialEq)]
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum GameOverReason {
+        /* spawned piece overlaps the stack */
+        BlockOut,
+        /* piece locked entirely above visible area */
+        LockOut,
+        /* garbage pushed blocks out of the buffer */
+        GarbageTopOut,
+        GoalReached,
+        /* time limited mode is over */
+        TimeUp,
+        UserQuit,
+    }
+
+    impl fmt::Display for GameOverReason {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let result = match self {
+                GameOverReason::BlockOut => "Block out",
+                GameOverReason::LockOut => "Lock out",
+                GameOverReason::GarbageTopOut => "Top out",
+                GameOverReason::GoalReached => "Goal reached",
+                GameOverReason::TimeUp => "Time is up",
+                GameOverReason::UserQuit => "Quit",
+            };
+
+            write!(f, "{}", result)
+        }
+    }
+
+    #[derive(Clone, PartialEq, Debug)]
     pub enum Event {
         Timeout,
         KeyLeft,
         KeyRight,
         KeyTurn,
+        KeyTurnCcw,
+        KeyFlip,
         KeyDown,
         KeyDrop,
         KeyHold,
         KeyExit,
+        KeyPause,
     }
 
     impl fmt::Display for Event {
@@ -67,32 +120,118 @@ pub mod engine {
                 Event::KeyDown => "⬇️",
                 Event::KeyExit => "🚪",
                 Event::KeyTurn => "🔁",
+                Event::KeyTurnCcw => "🔄",
+                Event::KeyFlip => "🔃",
                 Event::KeyDrop => "⬆️",
                 Event::KeyHold => "✋",
+                Event::KeyPause => "⏸️",
             };
 
             write!(f, "{}", result)
         }
     }
 
-    #[derive(Clone, PartialEq)]
+    /* Ultra lasts two minutes and sprint goes for 40 lines unless told otherwise */
+    pub const ULTRA_SECONDS: u32 = 120;
+    pub const MAX_ULTRA_SECONDS: u32 = 3600;
+    pub const SPRINT_LINES: u32 = 40;
+    pub const MAX_LINE_GOAL: u32 = 1000;
+    /* Cheese race digs through 18 garbage rows, no more than 10 of them
+     * are on the playfield at once */
+    pub const CHEESE_ROWS: u32 = 18;
+    const CHEESE_HEIGHT: i8 = 10;
+    /* Survival garbage rises every 8 seconds at level 0, half a second
+     * sooner with every level down to a second */
+    const SURVIVAL_RISE_FRAMES: u32 = 8 * FRAME_RATE;
+    const SURVIVAL_MIN_RISE_FRAMES: u32 = FRAME_RATE;
+
+    #[derive(Clone, PartialEq, Debug)]
     pub enum Mode {
         Marathon,
-        Sprint,
+        /* clear given number of lines as fast as possible */
+        Sprint{lines: u32},
+        /* score as much as possible in given time */
+        Ultra{seconds: u32},
+        /* dig through given number of garbage rows as fast as possible */
+        Cheese{rows: u32},
+        /* last as long as possible while garbage rises */
+        Survival,
+        /* reach level 999 under gravity going up to 20G */
+        Master,
+        /* endless practice, board starts over on top-out */
+        Zen,
+    }
+
+    impl Mode {
+        /* Frames of play before game ends */
+        pub fn time_limit(self: &Self) -> Option<u32> {
+            match self {
+                Mode::Ultra{seconds} => Some(seconds.saturating_mul(FRAME_RATE)),
+                _ => None,
+            }
+        }
+
+        /* Frames between garbage rows rising at given level */
+        pub fn rise_interval(self: &Self, level: i8) -> Option<u32> {
+            match self {
+                Mode::Survival => {
+                    let speedup = level.max(0) as u32 * FRAME_RATE / 2;
+                    Some(SURVIVAL_RISE_FRAMES.saturating_sub(speedup).max(SURVIVAL_MIN_RISE_FRAMES))
+                },
+                _ => None,
+            }
+        }
     }
 
     impl fmt::Display for Mode {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             let result = match self {
                 Mode::Marathon => "Marathon",
-                Mode::Sprint => "Sprint",
+                Mode::Sprint{..} => "Sprint",
+                Mode::Ultra{..} => "Ultra",
+                Mode::Cheese{..} => "Cheese",
+                Mode::Survival => "Survival",
+                Mode::Master => "Master",
+                Mode::Zen => "Zen",
             };
 
             write!(f, "{}", result)
         }
     }
 
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum ClearType {
+        Single,
+        Double,
+        Triple,
+        /* four or more, pentominoes can clear five */
+        Tetris,
+    }
+
+    /* What happened inside calculate_frame, positions are layout top left corners */
+    #[derive(Clone, PartialEq, Debug)]
+    pub enum GameEvent {
+        Spawned{shape: Shape},
+        /* by player or by gravity, hard drop is a single move */
+        Moved{col: i8, row: i8},
+        Rotated{orientation: Orientation, col: i8, row: i8},
+        Held{held: Shape, active: Shape},
+        Locked{shape: Shape, orientation: Orientation, col: i8, row: i8},
+        /* chain grows with every cascade step after the first clear */
+        LinesCleared{rows: Vec<i8>, clear: ClearType, chain: u8},
+        LevelUp{level: i8},
+        /* zen mode top-out, resets counts them all */
+        BoardReset{resets: u32},
+        GameOver{reason: GameOverReason},
+    }
+
+    pub trait GameObserver {
+        fn notify(self: &mut Self, event: &GameEvent);
+    }
+
+    #[derive(Clone)]
     pub struct Game {
+        config: Config,
         playfield: PlayfieldCtrl,
         state: State,
         next_tetro: NextTetroCtrl,
@@ -101,19 +240,106 @@ pub mod engine {
         fall: Fall,
         playtime: PlaytimeCtrl,
         end_game: EndgameCtrl,
+        pause: PauseCtrl,
+        garbage: Garbage,
+        /* frames since last garbage row rose */
+        rise_frames: u32,
+        /* master mode frames left before next piece appears */
+        are: u32,
+        /* state to go back to after pause */
+        resume_state: State,
+        /* tetrominoes generated so far */
+        pieces: u32,
+        /* collected only after subscribe */
+        events: Option<Vec<GameEvent>>,
+    }
+
+    #[derive(Debug)]
+    pub struct ConfigError {
+        pub reason: String,
+    }
+
+    /* Game on empty playfield of configured size */
+    pub fn new_game(config: Config) -> Result<Game, ConfigError> {
+        check_config(&config)?;
+        let playfield = playfield::Playfield::new(playfield::Storage::new(config.dimensions));
+        Ok(start_game(config, playfield))
     }
 
-    pub fn new_game(config: Config, playfield: playfield::Playfield) -> Game {
+    /* Game on prepared playfield, e.g. an imported board */
+    pub fn new_game_with_playfield(config: Config, playfield: playfield::Playfield) -> Result<Game, ConfigError> {
+        check_config(&config)?;
+        let (found, expected) = (playfield.dimensions(), config.dimensions);
+        if found != expected {
+            return Err(ConfigError{reason: format!("playfield {}x{} doesn't match config {}x{}",
+                                                   found.width, found.height, expected.width, expected.height)});
+        }
+        Ok(start_game(config, playfield))
+    }
+
+    /* Library callers may pass anything, both constructors check it first */
+    fn check_config(config: &Config) -> Result<(), ConfigError> {
+        let dimensions = config.dimensions;
+        if !dimensions.is_valid() {
+            return Err(ConfigError{reason: format!("unsupported playfield size {}x{}", dimensions.width, dimensions.height)});
+        }
+        /* piece set may have pieces too wide for a narrow playfield, they would block out on spawn */
+        let empty = playfield::Playfield::new(playfield::Storage::new(dimensions));
+        for shape in config.rotation_system.shapes() {
+            let piece = next_tetro_ctrl::spawn(shape.clone(), config.rotation_system, dimensions);
+            if !empty.can_place(&piece.tetro, &piece.coords) {
+                return Err(ConfigError{reason: format!("piece {} doesn't fit playfield {}x{}", shape.name(), dimensions.width, dimensions.height)});
+            }
+        }
+        Ok(())
+    }
+
+    fn start_game(config: Config, playfield: playfield::Playfield) -> Game {
         let next_queue_size = std::cmp::min(config.next_queue_size as usize, MAX_PREVIEW_SIZE);
         Game {
-            playfield: PlayfieldCtrl::new(playfield, config.no_ghost),
+            config: config.clone(),
+            playfield: PlayfieldCtrl::new(playfield, config.no_ghost, config.clear_mode),
             static_ctrl: StaticCtrl::new(next_queue_size),
-            next_tetro: NextTetroCtrl::new(next_queue_size),
+            next_tetro: NextTetroCtrl::new(next_queue_size, config.rotation_system, config.dimensions, config.seed),
             score: ScoreCtrl::new(config.level as i8, config.mode.clone()),
             state: State::CompletionPhase,
             fall: Fall::new(),
-            playtime: PlaytimeCtrl::new(),
-            end_game: EndgameCtrl::new(config.mode),
+            playtime: PlaytimeCtrl::new(config.mode.time_limit()),
+            end_game: EndgameCtrl::new(),
+            pause: PauseCtrl::new(next_queue_size),
+            garbage: Garbage::new(config.seed),
+            rise_frames: 0,
+            are: 0,
+            resume_state: State::CompletionPhase,
+            pieces: 0,
+            events: None,
+        }
+    }
+
+    /* Start collecting game events, until then they are not kept */
+    pub fn subscribe(game: &mut Game) {
+        if game.events.is_none() {
+            game.events = Some(Vec::new());
+        }
+    }
+
+    /* Events since last call, oldest first */
+    pub fn drain_events(game: &mut Game) -> Vec<GameEvent> {
+        match &mut game.events {
+            Some(events) => std::mem::take(events),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn dispatch_events(game: &mut Game, observer: &mut dyn GameObserver) {
+        for event in drain_events(game).iter() {
+            observer.notify(event);
+        }
+    }
+
+    fn emit(game: &mut Game, event: GameEvent) {
+        if let Some(events) = &mut game.events {
+            events.push(event);
         }
     }
 
@@ -122,16 +348,232 @@ pub mod engine {
     }
 
     pub fn final_score(game: &Game) -> Score {
-        game.score.score()
+        game.score.score(game.pieces)
+    }
+
+    pub fn playfield(game: &Game) -> &playfield::Playfield {
+        game.playfield.playfield()
+    }
+
+    pub fn config(game: &Game) -> &Config {
+        &game.config
+    }
+
+    pub fn is_paused(game: &Game) -> bool {
+        game.state == State::Paused
+    }
+
+    /* Read only view of the game for tools, bots and other front ends */
+
+    pub fn state(game: &Game) -> State {
+        game.state.clone()
+    }
+
+    /* Locked cell, NoShape outside of playfield */
+    pub fn cell(game: &Game, coords: &playfield::Coords) -> Shape {
+        let dimensions = game.config.dimensions;
+        if (0..dimensions.width).contains(&coords.col) && (0..dimensions.total_height()).contains(&coords.row) {
+            game.playfield.playfield().storage().cell(coords).clone()
+        } else {
+            Shape::NoShape
+        }
+    }
+
+    /* Falling piece with its layout top left corner and orientation, None between pieces */
+    pub fn active(game: &Game) -> Option<&playfield::FieldTetrimino> {
+        match game.playfield.active_shape() {
+            Shape::NoShape => None,
+            _ => Some(game.playfield.active()),
+        }
+    }
+
+    /* Where active piece lands if dropped */
+    pub fn ghost(game: &Game) -> Option<playfield::FieldTetrimino> {
+        active(game).map(|_| game.playfield.ghost())
+    }
+
+    pub fn next_queue(game: &Game) -> &[Shape] {
+        game.next_tetro.preview()
+    }
+
+    /* Hold swaps active piece with the next one, so held piece is the
+     * first in next queue. None if hold wasn't used for current piece. */
+    pub fn hold(game: &Game) -> Option<Shape> {
+        game.next_tetro.held()
+    }
+
+    pub fn level(game: &Game) -> i8 {
+        game.score.level()
+    }
+
+    /* Frames of play, pauses and line clear animations don't count */
+    pub fn frames(game: &Game) -> u32 {
+        game.playtime.frames()
+    }
+
+    pub fn pieces(game: &Game) -> u32 {
+        game.pieces
+    }
+
+    pub fn lines(game: &Game) -> u32 {
+        game.score.lines()
+    }
+
+    /* None while game is still running */
+    pub fn game_over_reason(game: &Game) -> Option<GameOverReason> {
+        game.end_game.reason()
+    }
+
+    /* Board was hidden during pause */
+    fn unpause(game: &mut Game) -> State {
+        game.pause.stop();
+        game.playfield.redraw();
+        game.next_tetro.redraw();
+        game.resume_state.clone()
+    }
+
+    /* First press pauses, second one starts countdown, pressing during countdown pauses again */
+    fn toggle_pause(game: &mut Game) {
+        if game.state != State::Paused {
+            game.resume_state = game.state.clone();
+            game.state = State::Paused;
+            game.pause.pause();
+        } else if game.pause.counting_down() {
+            game.pause.pause();
+        } else {
+            game.pause.resume();
+        }
+    }
+
+    fn game_over(game: &mut Game, reason: GameOverReason) -> (State, bool) {
+        let top_out = reason == GameOverReason::BlockOut || reason == GameOverReason::LockOut || reason == GameOverReason::GarbageTopOut;
+        if game.config.mode == Mode::Zen && top_out && game.end_game.reason().is_none() {
+            return reset_board(game);
+        }
+        if game.end_game.reason().is_none() {
+            emit(game, GameEvent::GameOver{reason: reason});
+        }
+        game.end_game.finish(reason);
+        (State::GameOver, true)
+    }
+
+    /* Zen mode empties the board and goes on with the next piece,
+     * score and statistics stay */
+    fn reset_board(game: &mut Game) -> (State, bool) {
+        game.playfield.reset_board();
+        game.fall.reset();
+        game.end_game.board_reset();
+        emit(game, GameEvent::BoardReset{resets: game.end_game.resets()});
+        (State::GenerationPhase, true)
+    }
+
+    /* Board starts over this many times in zen mode */
+    pub fn board_resets(game: &Game) -> u32 {
+        game.end_game.resets()
+    }
+
+    /* Push garbage rows with given hole columns under the stack */
+    pub fn add_garbage(game: &mut Game, count: i8, holes: &[i8]) {
+        if game.state == State::GameOver {
+            return;
+        }
+        if !game.playfield.add_garbage(count, holes) {
+            game.state = game_over(game, GameOverReason::GarbageTopOut).0;
+        }
+    }
+
+    /* Cheese race keeps garbage on the playfield until all its rows are dealt.
+     * Returns false on top-out. */
+    fn refill_garbage(game: &mut Game) -> bool {
+        let rows = match game.config.mode {
+            Mode::Cheese{rows} => rows,
+            _ => return true,
+        };
+        let dimensions = game.config.dimensions;
+        let height = std::cmp::min(CHEESE_HEIGHT, dimensions.height / 2);
+        let on_field = (0..dimensions.total_height()).filter(|row| game.playfield.playfield().garbage_row(*row)).count() as i8;
+        let mut missing = std::cmp::max(height - on_field, 0) as u32;
+        while missing > 0 && game.garbage.rows() < rows {
+            let hole = game.garbage.next_hole(dimensions.width);
+            if !game.playfield.add_garbage(1, &[hole]) {
+                return false;
+            }
+            missing -= 1;
+        }
+        true
+    }
+
+    /* Survival pushes a garbage row up when its time comes.
+     * Returns false on top-out. */
+    fn rise_garbage(game: &mut Game) -> bool {
+        let interval = match game.config.mode.rise_interval(game.score.level()) {
+            Some(interval) => interval,
+            None => return true,
+        };
+        game.rise_frames += 1;
+        if game.rise_frames < interval {
+            return true;
+        }
+        game.rise_frames = 0;
+        let hole = game.garbage.next_hole(game.config.dimensions.width);
+        game.playfield.add_garbage(1, &[hole])
+    }
+
+    /* Master gravity may drop the piece several cells a frame, at 20G
+     * it goes all the way down. Piece locks after lock delay on the ground,
+     * falling a cell starts the delay over. */
+    fn master_gravity(game: &mut Game) -> (State, bool) {
+        let level = game.score.master_level();
+        let gravity = master::gravity(level);
+        let drops = if gravity >= master::GRAVITY_20G {
+            game.config.dimensions.total_height() as u32
+        } else {
+            game.fall.gravity_drops(gravity)
+        };
+        let mut fallen = false;
+        for _ in 0..drops {
+            if !game.playfield.move_active(playfield::Dir::Down).0 {
+                break;
+            }
+            fallen = true;
+        }
+        if fallen {
+            game.fall.lock_frames = 0;
+            let active = game.playfield.active();
+            let moved = GameEvent::Moved{col: active.coords.col, row: active.coords.row};
+            emit(game, moved);
+        }
+
+        if game.playfield.playfield().has_fall_space(game.playfield.active()) {
+            (State::FallingPhase, false)
+        } else if game.fall.lock_tick(master::timings(level).lock_delay) {
+            (State::PatternPhase, true)
+        } else {
+            (State::LockedPhase, false)
+        }
+    }
+
+    /* Next draw_frame shows everything, not only what has changed */
+    pub fn redraw(game: &mut Game) {
+        game.static_ctrl.redraw();
+        game.score.redraw();
+        game.playtime.redraw();
+        game.next_tetro.redraw();
+        game.playfield.redraw();
+        game.end_game.redraw();
+        game.pause.redraw();
     }
 
     pub fn draw_frame(game: &mut Game, view: &mut impl View) {
         game.static_ctrl.show(view);
         game.score.show(view);
         game.playtime.show(view);
-        game.next_tetro.show(view);
-        game.playfield.show(view);
+        if game.state != State::Paused {
+            game.next_tetro.show(view);
+            game.playfield.show(view);
+        }
         game.end_game.show(view);
+        game.pause.show(view);
     }
 
     fn handle_user_move(game: &mut Game, event: Event) -> (State, bool) {
@@ -139,24 +581,38 @@ pub mod engine {
             Event::KeyDown => game.playfield.move_active(playfield::Dir::Down),
             Event::KeyLeft => game.playfield.move_active(playfield::Dir::Left),
             Event::KeyRight => game.playfield.move_active(playfield::Dir::Right),
-            Event::KeyTurn => game.playfield.turn_active(),
+            Event::KeyTurn => game.playfield.turn_active(Rotation::Clockwise),
+            Event::KeyTurnCcw => game.playfield.turn_active(Rotation::CounterClockwise),
+            Event::KeyFlip => game.playfield.turn_active(Rotation::Flip),
             Event::KeyDrop => {
                 let (move_success, _) = game.playfield.move_active(playfield::Dir::Down);
-                while game.playfield.move_active(playfield::Dir::Down) == (true, true || false) {};
+                while game.playfield.move_active(playfield::Dir::Down) == (true, true) {};
                 (move_success, false)
             },
             Event::KeyHold => {
                 let active_shape = game.playfield.active_shape();
 
-                match game.next_tetro.swap(active_shape) {
-                    Ok(tetro) => {game.playfield.new_active(tetro);},
-                    _ => /* do nothing */{},
-                };
+                if let Ok(tetro) = game.next_tetro.swap(active_shape.clone()) {
+                    emit(game, GameEvent::Held{held: active_shape, active: tetro.tetro.shape.clone()});
+                    game.playfield.new_active(tetro);
+                }
                 (true, true)
             },
             _ => (true, true),
         };
 
+        let active = game.playfield.active();
+        let (col, row, orientation) = (active.coords.col, active.coords.row, active.tetro.orientation());
+        match event {
+            Event::KeyDown | Event::KeyLeft | Event::KeyRight | Event::KeyDrop if move_success => {
+                emit(game, GameEvent::Moved{col: col, row: row});
+            },
+            Event::KeyTurn | Event::KeyTurnCcw | Event::KeyFlip if move_success => {
+                emit(game, GameEvent::Rotated{orientation: orientation, col: col, row: row});
+            },
+            _ => {},
+        }
+
         if !move_success && event == Event::KeyDown {
             (State::PatternPhase, true)
         } else if move_success && !fall_space {
@@ -170,22 +626,45 @@ pub mod engine {
     pub fn calculate_frame(game: &mut Game, event: Event) {
         let mut reschedule = true;
         if event == Event::KeyExit {
-            game.state = State::GameOver;
+            if game.state == State::Paused {
+                unpause(game);
+            }
+            game.state = game_over(game, GameOverReason::UserQuit).0;
         }
-        if event == Event::Timeout && game.state != State::AnimationPhase && game.state != State::GameOver {
+        if event == Event::KeyPause && game.state != State::GameOver {
+            toggle_pause(game);
+        }
+        if event == Event::Timeout && game.state != State::AnimationPhase && game.state != State::GameOver && game.state != State::Paused {
             game.playtime.update();
+            if game.playtime.expired() {
+                game.state = game_over(game, GameOverReason::TimeUp).0;
+            } else if !rise_garbage(game) {
+                game.state = game_over(game, GameOverReason::GarbageTopOut).0;
+            }
         }
         while reschedule {
             let result = match game.state {
+                State::GenerationPhase if game.are > 0 => {
+                    if event == Event::Timeout {
+                        game.are -= 1;
+                    }
+                    (State::GenerationPhase, game.are == 0)
+                },
                 State::GenerationPhase => {
                     /* generation phase */
                     if game.playfield.new_active(game.next_tetro.pop()) {
-                        /* tetro can be placed in start position */
+                        /* tetro can be placed in start position, only then it counts */
+                        game.pieces += 1;
+                        game.score.piece_spawned();
+                        emit(game, GameEvent::Spawned{shape: game.playfield.active_shape()});
                         (State::FallingPhase, true)
                     } else {
-                        (State::GameOver, true)
+                        game_over(game, GameOverReason::BlockOut)
                     }
                 },
+                State::FallingPhase | State::LockedPhase if event == Event::Timeout && game.config.mode == Mode::Master => {
+                    master_gravity(game)
+                },
                 State::FallingPhase | State::LockedPhase => {
                     /* replace timeout drop with KeyDown event to simplify further handling */
                     let event = if event == Event::Timeout && game.fall.inc_frame_counter(game.score.level()) {
@@ -197,9 +676,21 @@ pub mod engine {
                     handle_user_move(game, event.clone())
                 },
                 State::PatternPhase => {
+                    let lock_out = game.playfield.active_above_visible();
+                    let active = game.playfield.active();
+                    let locked = GameEvent::Locked{shape: active.tetro.shape.clone(), orientation: active.tetro.orientation(),
+                                                   col: active.coords.col, row: active.coords.row};
+                    emit(game, locked);
                     game.playfield.place_active();
-                    game.playfield.start_animation();
-                    (State::AnimationPhase, true)
+                    if lock_out {
+                        game_over(game, GameOverReason::LockOut)
+                    } else {
+                        if game.config.mode == Mode::Master {
+                            game.playfield.set_clear_delay(master::timings(game.score.master_level()).line_clear);
+                        }
+                        game.playfield.start_animation();
+                        (State::AnimationPhase, true)
+                    }
                 },
                 State::AnimationPhase => {
                     if event == Event::Timeout && !game.playfield.animate() {
@@ -210,16 +701,47 @@ pub mod engine {
                 },
                 State::CompletionPhase => {
                     /* elimimination phase */
+                    let rows = game.playfield.filled_rows();
+                    let garbage_rows = rows.iter().filter(|row| game.playfield.playfield().garbage_row(**row)).count();
                     let removed_rows_count = game.playfield.remove_filled();
+                    if removed_rows_count > 0 {
+                        let clear = match removed_rows_count {
+                            1 => ClearType::Single,
+                            2 => ClearType::Double,
+                            3 => ClearType::Triple,
+                            _ => ClearType::Tetris,
+                        };
+                        emit(game, GameEvent::LinesCleared{rows: rows, clear: clear, chain: game.playfield.chain()});
+                    }
                     /* completion phase */
-                    game.score.update(removed_rows_count as u8, game.playtime.frames());
+                    let level = game.score.level();
+                    game.score.update_chain(removed_rows_count as u8, game.playfield.chain(), game.playtime.frames());
+                    game.score.update_garbage(garbage_rows as u32);
+                    if game.score.level() > level {
+                        emit(game, GameEvent::LevelUp{level: game.score.level()});
+                    }
                     game.fall.reset();
-                    if game.score.goal_complete() {
-                        (State::GameOver, true)
+                    if removed_rows_count > 0 && game.playfield.start_animation() {
+                        /* cascade has completed more lines, they are the next step of chain */
+                        (State::AnimationPhase, true)
+                    } else if game.score.goal_complete() {
+                        game_over(game, GameOverReason::GoalReached)
+                    } else if !refill_garbage(game) {
+                        game_over(game, GameOverReason::GarbageTopOut)
                     } else {
+                        if game.config.mode == Mode::Master {
+                            game.are = master::timings(game.score.master_level()).are;
+                        }
                         (State::GenerationPhase, true)
                     }
                 },
+                State::Paused => {
+                    if event == Event::Timeout && game.pause.tick() {
+                        (unpause(game), false)
+                    } else {
+                        (State::Paused, false)
+                    }
+                },
                 State::GameOver => {
                     game.score.update(0, game.playtime.frames());
                     game.playtime.update();
@@ -231,4 +753,711 @@ pub mod engine {
             reschedule = result.1;
         }
     }
+
+    /* Saved game is a sequence of tlv records: version, config records
+     * shared with replays (tags 2-4 and 7-9), then state of every part of the game.
+     * Playfield with active piece is stored as board text, a line per record. */
+    const SAVE_VERSION: u8 = 1;
+
+    const TAG_VERSION: tlv::Type = 1;
+    const TAG_STATE: tlv::Type = 16;
+    const TAG_BOARD: tlv::Type = 17;
+    const TAG_BAG: tlv::Type = 18;
+    const TAG_SCORE: tlv::Type = 19;
+    const TAG_FALL: tlv::Type = 20;
+    const TAG_PLAYTIME: tlv::Type = 21;
+    const TAG_END: tlv::Type = 22;
+    const TAG_GARBAGE: tlv::Type = 23;
+    const TAG_MASTER: tlv::Type = 24;
+    const TAG_RESETS: tlv::Type = 25;
+    const TAG_RISE: tlv::Type = 26;
+
+    /* Position in table is state code in saved game */
+    const STATES: [State; 8] = [
+        State::GenerationPhase, State::CompletionPhase, State::FallingPhase, State::LockedPhase,
+        State::PatternPhase, State::AnimationPhase, State::Paused, State::GameOver,
+    ];
+
+    #[derive(Debug)]
+    pub struct SaveError {
+        pub reason: String,
+    }
+
+    impl From<std::io::Error> for SaveError {
+        fn from(e: std::io::Error) -> Self {
+            SaveError{reason: e.to_string()}
+        }
+    }
+
+    impl From<ReplayError> for SaveError {
+        fn from(e: ReplayError) -> Self {
+            SaveError{reason: e.reason}
+        }
+    }
+
+    impl From<ConfigError> for SaveError {
+        fn from(e: ConfigError) -> Self {
+            SaveError{reason: e.reason}
+        }
+    }
+
+    impl From<ParseError> for SaveError {
+        fn from(e: ParseError) -> Self {
+            SaveError{reason: format!("board line {}: {}", e.line, e.reason)}
+        }
+    }
+
+    fn encode(t: tlv::Type, v: &[u8], ostream: &mut dyn Write) -> Result<(), SaveError> {
+        if v.len() > tlv::Length::MAX as usize {
+            return Err(SaveError{reason: format!("record {} is too long", t)});
+        }
+        tlv::encode(t, v.len() as tlv::Length, v, ostream)?;
+        Ok(())
+    }
+
+    fn parse_shapes(names: &[u8], rotation_system: &dyn RotationSystem) -> Result<Vec<Shape>, SaveError> {
+        String::from_utf8_lossy(names).chars().map(|c| match board::shape_by_name(c, rotation_system) {
+            Some(shape) => Ok(shape),
+            None => Err(SaveError{reason: format!("unknown piece {}", c)}),
+        }).collect()
+    }
+
+    pub fn save_game(game: &Game, ostream: &mut dyn Write) -> Result<(), SaveError> {
+        if game.state == State::GameOver {
+            return Err(SaveError{reason: "game is over".to_string()});
+        }
+        encode(TAG_VERSION, &[SAVE_VERSION], ostream)?;
+        replay::write_config(&game.config, ostream)?;
+
+        let mut state = vec![replay::code(&STATES, &game.state), replay::code(&STATES, &game.resume_state), game.playfield.chain()];
+        state.extend_from_slice(&game.pieces.to_le_bytes());
+        encode(TAG_STATE, &state, ostream)?;
+
+        let mut board = Board::new(game.playfield.playfield().storage().clone());
+        if game.playfield.active_shape() != Shape::NoShape {
+            board.active = Some(game.playfield.active().clone());
+        }
+        for line in board.to_text().lines() {
+            encode(TAG_BOARD, line.as_bytes(), ostream)?;
+        }
+
+        let bag = game.next_tetro.bag_state();
+        let mut v = vec![bag.index as u8, bag.held as u8];
+        v.extend_from_slice(&bag.shuffles.to_le_bytes());
+        v.extend_from_slice(bag.shapes.iter().map(|s| s.name()).collect::<String>().as_bytes());
+        encode(TAG_BAG, &v, ostream)?;
+
+        let mut v = vec![game.score.level() as u8];
+        for value in [game.score.points(), game.score.lines()].iter().chain(game.score.clear_statistic().iter()) {
+            v.extend_from_slice(&value.to_le_bytes());
+        }
+        encode(TAG_SCORE, &v, ostream)?;
+
+        encode(TAG_FALL, &[game.fall.frame_counter as u8, game.fall.lock_delay_triggered as u8], ostream)?;
+        encode(TAG_PLAYTIME, &game.playtime.frames().to_le_bytes(), ostream)?;
+        let mut v = game.garbage.rows().to_le_bytes().to_vec();
+        v.extend_from_slice(&game.score.garbage_cleared().to_le_bytes());
+        encode(TAG_GARBAGE, &v, ostream)?;
+        encode(TAG_RISE, &game.rise_frames.to_le_bytes(), ostream)?;
+        let mut v = Vec::new();
+        for value in [game.score.master_level(), game.are, game.fall.gravity_fraction, game.fall.lock_frames].iter() {
+            v.extend_from_slice(&value.to_le_bytes());
+        }
+        encode(TAG_MASTER, &v, ostream)?;
+        encode(TAG_RESETS, &game.end_game.resets().to_le_bytes(), ostream)?;
+        encode(TAG_END, &[], ostream)?;
+        Ok(())
+    }
+
+    pub fn load_game(istream: &mut dyn Read) -> Result<Game, SaveError> {
+        let (t, _, v) = tlv::decode_one(istream)?;
+        if t != TAG_VERSION || v != [SAVE_VERSION] {
+            return Err(SaveError{reason: "not a saved game or unsupported version".to_string()});
+        }
+
+        let mut config = replay::ConfigReader::default();
+        let mut records: Vec<(tlv::Type, Vec<u8>)> = Vec::new();
+        let mut board_text = String::new();
+        loop {
+            let (t, _, v) = tlv::decode_one(istream)?;
+            if config.read(t, &v)? {
+                continue;
+            }
+            match t {
+                TAG_BOARD => {
+                    board_text += &String::from_utf8_lossy(&v);
+                    board_text.push('\n');
+                },
+                TAG_END => break,
+                _ => records.push((t, v)),
+            }
+        }
+        let config = config.config()?;
+        let record = |tag: tlv::Type, length: Option<usize>| -> Result<&[u8], SaveError> {
+            /* the last one wins, records from newer versions of the same format are skipped */
+            match records.iter().rev().find(|(t, _)| *t == tag) {
+                Some((t, v)) => {
+                    if let Some(length) = length {
+                        replay::check_length(*t, v, length)?;
+                    }
+                    Ok(v)
+                },
+                None => Err(SaveError{reason: format!("record {} is missing", tag)}),
+            }
+        };
+
+        let board = Board::parse(&board_text, config.rotation_system)?;
+        let rotation_system = config.rotation_system;
+        let mut game = new_game_with_playfield(config, playfield::Playfield::new(board.storage))?;
+
+        let state = record(TAG_STATE, Some(7))?;
+        game.state = replay::decode(&STATES, state[0])?;
+        game.resume_state = replay::decode(&STATES, state[1])?;
+        if game.state == State::GameOver || game.resume_state == State::GameOver || game.resume_state == State::Paused {
+            return Err(SaveError{reason: "bad game state".to_string()});
+        }
+        if !game.playfield.restore(board.active, state[2]) {
+            return Err(SaveError{reason: "active piece doesn't fit".to_string()});
+        }
+        game.pieces = replay::read_u32(&state[3..]);
+
+        let bag = record(TAG_BAG, None)?;
+        if bag.len() < 6 {
+            return Err(SaveError{reason: "bag record is too short".to_string()});
+        }
+        let bag = BagState{shapes: parse_shapes(&bag[6..], rotation_system)?, index: bag[0] as usize,
+                           held: bag[1] != 0, shuffles: replay::read_u32(&bag[2..])};
+        if !game.next_tetro.restore(bag) {
+            return Err(SaveError{reason: "bag doesn't match piece set".to_string()});
+        }
+
+        let score = record(TAG_SCORE, Some(25))?;
+        let mut values = [0u32; 6];
+        for (index, value) in values.iter_mut().enumerate() {
+            *value = replay::read_u32(&score[1 + 4 * index..]);
+        }
+        let frames = replay::read_u32(record(TAG_PLAYTIME, Some(4))?);
+        game.score.restore(score[0] as i8, values[0], values[1], [values[2], values[3], values[4], values[5]], frames);
+        game.playtime.restore(frames);
+
+        /* saves made before cheese race have no garbage record */
+        if records.iter().any(|(t, _)| *t == TAG_GARBAGE) {
+            let garbage = record(TAG_GARBAGE, Some(8))?;
+            game.garbage.restore(replay::read_u32(garbage), game.config.dimensions.width);
+            game.score.update_garbage(replay::read_u32(&garbage[4..]));
+        }
+        if records.iter().any(|(t, _)| *t == TAG_RISE) {
+            game.rise_frames = replay::read_u32(record(TAG_RISE, Some(4))?);
+        }
+        if records.iter().any(|(t, _)| *t == TAG_MASTER) {
+            let values = record(TAG_MASTER, Some(16))?;
+            game.score.restore_master_level(replay::read_u32(values));
+            game.are = replay::read_u32(&values[4..]);
+            game.fall.gravity_fraction = replay::read_u32(&values[8..]);
+            game.fall.lock_frames = replay::read_u32(&values[12..]);
+        }
+        if records.iter().any(|(t, _)| *t == TAG_RESETS) {
+            game.end_game.restore(replay::read_u32(record(TAG_RESETS, Some(4))?));
+        }
+
+        let fall = record(TAG_FALL, Some(2))?;
+        game.fall.frame_counter = fall[0] as i8;
+        game.fall.lock_delay_triggered = fall[1] != 0;
+
+        if game.state == State::Paused {
+            game.pause.pause();
+        }
+        if game.state == State::AnimationPhase || game.resume_state == State::AnimationPhase {
+            /* blinking starts over */
+            game.playfield.start_animation();
+        }
+        Ok(game)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::rotation::{SRS};
+        use crate::board::{Board};
+        use crate::fall::{FRAME_RATE};
+        use crate::figures::figures::{Orientation};
+
+        fn config() -> Config {
+            Config{no_ghost: false, level: 0, next_queue_size: 4, mode: Mode::Marathon,
+                   rotation_system: &SRS, dimensions: Default::default(), clear_mode: playfield::ClearMode::Naive, seed: 0}
+        }
+
+        #[test]
+        fn block_out() {
+            let mut text = String::new();
+            for _ in 0..playfield::HEIGHT {
+                text += "GGGGGGGGG.\n";
+            }
+            let board = Board::parse(&text, &SRS).unwrap();
+            let mut game = new_game_with_playfield(config(), playfield::Playfield::new(board.storage)).unwrap();
+            calculate_frame(&mut game, Event::Timeout);
+            assert_eq!(is_finished(&game), true);
+            assert_eq!(game_over_reason(&game), Some(GameOverReason::BlockOut));
+            /* blocked piece never entered play */
+            assert_eq!(pieces(&game), 0);
+        }
+
+        #[test]
+        fn playfield_must_match_config() {
+            let dimensions = playfield::Dimensions::new(8, 16);
+            let small = playfield::Playfield::new(playfield::Storage::new(dimensions));
+            assert_eq!(new_game_with_playfield(config(), small.clone()).is_err(), true);
+            assert_eq!(new_game_with_playfield(Config{dimensions: dimensions, ..config()}, small).is_ok(), true);
+            /* empty playfield follows config */
+            assert_eq!(playfield(&new_game(Config{dimensions: dimensions, ..config()}).unwrap()).dimensions(), dimensions);
+        }
+
+        #[test]
+        fn pieces_must_fit_playfield() {
+            let pentominoes = crate::piece_set::system_by_name("pentomino").unwrap();
+            let narrow = Config{rotation_system: pentominoes, dimensions: playfield::Dimensions::new(4, 20), ..config()};
+            assert_eq!(new_game(narrow.clone()).is_err(), true);
+            let board = playfield::Playfield::new(playfield::Storage::new(narrow.dimensions));
+            assert_eq!(new_game_with_playfield(narrow, board).is_err(), true);
+            assert_eq!(new_game(Config{rotation_system: pentominoes, ..config()}).is_ok(), true);
+        }
+
+        #[test]
+        fn unsupported_dimensions() {
+            for dimensions in [playfield::Dimensions::new(17, 20), playfield::Dimensions::new(10, 2)].iter() {
+                let config = Config{dimensions: *dimensions, ..config()};
+                assert_eq!(new_game(config.clone()).is_err(), true);
+                assert_eq!(crate::headless::Simulator::new(config).is_err(), true);
+            }
+        }
+
+        #[test]
+        fn lock_out() {
+            let mut game = new_game(config()).unwrap();
+            calculate_frame(&mut game, Event::Timeout);
+            assert_eq!(game_over_reason(&game), None);
+            /* garbage pushes falling piece out of visible area */
+            add_garbage(&mut game, playfield::HEIGHT, &[0]);
+            assert_eq!(is_finished(&game), false);
+            calculate_frame(&mut game, Event::KeyDown);
+            assert_eq!(is_finished(&game), true);
+            assert_eq!(game_over_reason(&game), Some(GameOverReason::LockOut));
+        }
+
+        #[test]
+        fn garbage_top_out_then_quit() {
+            let mut game = new_game(config()).unwrap();
+            calculate_frame(&mut game, Event::Timeout);
+            add_garbage(&mut game, playfield::HEIGHT + playfield::BUFFER_HEIGHT, &[0]);
+            assert_eq!(is_finished(&game), true);
+            calculate_frame(&mut game, Event::KeyExit);
+            assert_eq!(game_over_reason(&game), Some(GameOverReason::GarbageTopOut));
+        }
+
+        #[test]
+        fn same_seed_same_game() {
+            let play = |seed: u64| {
+                let mut game = new_game(Config{seed: seed, ..config()}).unwrap();
+                for moves in [[Event::KeyLeft, Event::KeyLeft], [Event::KeyTurn, Event::KeyRight], [Event::KeyHold, Event::KeyTurnCcw]].iter().cycle().take(6) {
+                    calculate_frame(&mut game, Event::Timeout);
+                    for event in moves.iter().chain([Event::KeyDrop].iter()) {
+                        calculate_frame(&mut game, event.clone());
+                    }
+                    /* wait for lock delay to expire */
+                    for _ in 0..FRAME_RATE {
+                        calculate_frame(&mut game, Event::Timeout);
+                    }
+                }
+                Board::new(playfield(&game).storage().clone()).to_text()
+            };
+            assert_eq!(play(7), play(7));
+            assert_ne!(play(7), play(8));
+        }
+
+        #[test]
+        fn pause_freezes_game() {
+            let mut game = new_game(config()).unwrap();
+            calculate_frame(&mut game, Event::Timeout);
+            let board = |game: &Game| Board::new(playfield(game).storage().clone()).to_text();
+            let before = board(&game);
+            let frames = game.playtime.frames();
+
+            calculate_frame(&mut game, Event::KeyPause);
+            assert_eq!(is_paused(&game), true);
+            /* gravity, playtime and moves are stopped, running gravity would have locked a few pieces by now */
+            for _ in 0..10 * FRAME_RATE {
+                calculate_frame(&mut game, Event::Timeout);
+            }
+            calculate_frame(&mut game, Event::KeyDrop);
+            calculate_frame(&mut game, Event::KeyDown);
+            assert_eq!(is_paused(&game), true);
+            assert_eq!(game.playtime.frames(), frames);
+            assert_eq!(board(&game), before);
+            assert_eq!(pieces(&game), 1);
+
+            calculate_frame(&mut game, Event::KeyPause);
+            for _ in 1..3 * FRAME_RATE {
+                calculate_frame(&mut game, Event::Timeout);
+            }
+            assert_eq!(is_paused(&game), true);
+            calculate_frame(&mut game, Event::Timeout);
+            assert_eq!(is_paused(&game), false);
+            assert_eq!(game.state == State::FallingPhase, true);
+            assert_eq!(game.playtime.frames(), frames);
+        }
+
+        struct Recorder {
+            shown: Vec<&'static str>,
+        }
+
+        impl View for Recorder {
+            fn show_subview(self: &mut Self, args: &crate::view::ShowArgs) {
+                use crate::view::ShowArgs;
+                self.shown.push(match args {
+                    ShowArgs::PlayfieldArgs{..} => "playfield",
+                    ShowArgs::NextTetroArgs{..} => "next",
+                    ShowArgs::PauseArgs{..} => "pause",
+                    _ => "other",
+                });
+            }
+        }
+
+        #[test]
+        fn board_hidden_while_paused() {
+            let mut game = new_game(config()).unwrap();
+            let mut view = Recorder{shown: Vec::new()};
+            calculate_frame(&mut game, Event::Timeout);
+            calculate_frame(&mut game, Event::KeyPause);
+            calculate_frame(&mut game, Event::KeyLeft);
+            draw_frame(&mut game, &mut view);
+            assert_eq!(view.shown.contains(&"pause"), true);
+            assert_eq!(view.shown.contains(&"playfield"), false);
+            assert_eq!(view.shown.contains(&"next"), false);
+
+            view.shown.clear();
+            calculate_frame(&mut game, Event::KeyExit);
+            draw_frame(&mut game, &mut view);
+            assert_eq!(view.shown.contains(&"playfield"), true);
+            assert_eq!(view.shown.contains(&"pause"), false);
+        }
+
+        #[test]
+        fn saved_game_goes_on_the_same_way() {
+            let mut game = new_game(Config{seed: 11, ..config()}).unwrap();
+            let moves = [Event::KeyLeft, Event::KeyTurn, Event::KeyHold, Event::KeyDrop, Event::KeyRight, Event::KeyDrop];
+            let play = |game: &mut Game, frames: u32| {
+                for frame in 0..frames {
+                    let event = if frame % 15 == 0 { moves[(frame / 15) as usize % moves.len()].clone() } else { Event::Timeout };
+                    calculate_frame(game, event);
+                }
+            };
+            play(&mut game, 20 * FRAME_RATE);
+            add_garbage(&mut game, 2, &[3]);
+            calculate_frame(&mut game, Event::KeyPause);
+
+            let mut buf: Vec<u8> = Vec::new();
+            assert_eq!(save_game(&game, &mut buf).is_ok(), true);
+            let mut loaded = load_game(&mut &buf[..]).unwrap();
+            assert_eq!(is_paused(&loaded), true);
+            assert_eq!(pieces(&loaded), pieces(&game));
+            assert_eq!(lines(&loaded), lines(&game));
+            assert_eq!(loaded.playtime.frames(), game.playtime.frames());
+
+            for game in [&mut game, &mut loaded].iter_mut() {
+                calculate_frame(game, Event::KeyPause);
+                play(game, 30 * FRAME_RATE);
+            }
+            let board = |game: &Game| {
+                let mut board = Board::new(playfield(game).storage().clone());
+                board.active = Some(game.playfield.active().clone());
+                board.to_text()
+            };
+            assert_eq!(board(&loaded), board(&game));
+            assert_eq!(pieces(&loaded), pieces(&game));
+            assert_eq!(format!("{:?}", final_score(&loaded)), format!("{:?}", final_score(&game)));
+        }
+
+        #[test]
+        fn broken_saved_games() {
+            let mut game = new_game(config()).unwrap();
+            calculate_frame(&mut game, Event::Timeout);
+            let mut buf: Vec<u8> = Vec::new();
+            assert_eq!(save_game(&game, &mut buf).is_ok(), true);
+            assert_eq!(load_game(&mut &buf[..buf.len() - 1]).is_err(), true);
+            assert_eq!(load_game(&mut &buf[1..]).is_err(), true);
+            /* finished games are not saved */
+            calculate_frame(&mut game, Event::KeyExit);
+            assert_eq!(save_game(&game, &mut Vec::new()).is_err(), true);
+        }
+
+        #[test]
+        fn older_saves_still_load() {
+            let mut game = new_game(Config{mode: Mode::Survival, ..config()}).unwrap();
+            for _ in 0..8 * FRAME_RATE + 10 {
+                calculate_frame(&mut game, Event::Timeout);
+            }
+            let mut buf: Vec<u8> = Vec::new();
+            assert_eq!(save_game(&game, &mut buf).is_ok(), true);
+            /* records added after the first cheese race save are optional */
+            let mut stream = &buf[..];
+            let mut older: Vec<u8> = Vec::new();
+            let mut garbage_length = 0;
+            loop {
+                let (t, l, v) = tlv::decode_one(&mut stream).unwrap();
+                if t == TAG_GARBAGE {
+                    garbage_length = l;
+                }
+                if t != TAG_RISE && t != TAG_MASTER && t != TAG_RESETS {
+                    tlv::encode(t, l, &v, &mut older).unwrap();
+                }
+                if t == TAG_END {
+                    break;
+                }
+            }
+            assert_eq!(garbage_length, 8);
+            let loaded = load_game(&mut &older[..]).unwrap();
+            assert_eq!(loaded.garbage.rows(), 1);
+            assert_eq!(loaded.rise_frames, 0);
+            assert_eq!(game.rise_frames > 0, true);
+            assert_eq!(load_game(&mut &buf[..]).unwrap().rise_frames, game.rise_frames);
+        }
+
+        #[test]
+        fn query_game_state() {
+            let mut game = new_game(config()).unwrap();
+            assert_eq!(active(&game).is_none(), true);
+            assert_eq!(state(&game), State::CompletionPhase);
+            calculate_frame(&mut game, Event::Timeout);
+            assert_eq!(state(&game), State::FallingPhase);
+            assert_eq!(next_queue(&game).len(), 4);
+            assert_eq!(hold(&game), None);
+
+            let first = active(&game).unwrap().tetro.shape.clone();
+            let next = next_queue(&game)[0].clone();
+            calculate_frame(&mut game, Event::KeyHold);
+            assert_eq!(active(&game).unwrap().tetro.shape, next);
+            assert_eq!(hold(&game), Some(first.clone()));
+            assert_eq!(next_queue(&game)[0], first);
+
+            calculate_frame(&mut game, Event::KeyTurn);
+            let piece = active(&game).unwrap().clone();
+            assert_eq!(piece.tetro.orientation(), Orientation::Right);
+            let ghost = ghost(&game).unwrap();
+            assert_eq!(ghost.coords.col, piece.coords.col);
+            assert_eq!(ghost.tetro.orientation(), piece.tetro.orientation());
+            calculate_frame(&mut game, Event::KeyDrop);
+            assert_eq!(active(&game).unwrap().coords.row, ghost.coords.row);
+            for _ in 0..FRAME_RATE {
+                calculate_frame(&mut game, Event::Timeout);
+            }
+            /* locked where ghost was */
+            let (coords, _) = ghost.tetro.clone().find(|(_, shape)| *shape != Shape::NoShape).unwrap();
+            let coords = playfield::Coords{row: ghost.coords.row - coords.row, col: ghost.coords.col + coords.col};
+            assert_eq!(cell(&game, &coords), ghost.tetro.shape);
+            /* walls and floor are empty */
+            for (row, col) in [(-1, 0), (0, -1), (0, playfield::WIDTH), (playfield::HEIGHT + playfield::BUFFER_HEIGHT, 0)].iter() {
+                assert_eq!(cell(&game, &playfield::Coords{row: *row, col: *col}), Shape::NoShape);
+            }
+            assert_eq!(pieces(&game), 2);
+            assert_eq!(level(&game), 0);
+            assert_eq!(lines(&game), 0);
+            assert_eq!(frames(&game) > FRAME_RATE, true);
+        }
+
+        #[test]
+        fn game_events() {
+            let set: &'static crate::piece_set::PieceSet =
+                Box::leak(Box::new(crate::piece_set::PieceSet::parse("i", "piece I 1 5 5\n####\n").unwrap()));
+            let board = Board::parse(&"size 4 20\n".chars().chain("GGG.\n".repeat(12).chars()).collect::<String>(), set).unwrap();
+            let config = Config{rotation_system: set, dimensions: playfield::Dimensions::new(4, 20), ..config()};
+            let mut game = new_game_with_playfield(config, playfield::Playfield::new(board.storage)).unwrap();
+            /* nothing is kept before subscribe */
+            calculate_frame(&mut game, Event::Timeout);
+            subscribe(&mut game);
+            assert_eq!(drain_events(&mut game), vec![]);
+
+            calculate_frame(&mut game, Event::KeyHold);
+            calculate_frame(&mut game, Event::KeyLeft);
+            let events = drain_events(&mut game);
+            assert_eq!(matches!(events[0], GameEvent::Held{..}), true);
+            /* piece is already at the left wall */
+            assert_eq!(events.len(), 1);
+
+            for _ in 0..3 {
+                /* standing piece is right above the gap */
+                calculate_frame(&mut game, Event::KeyTurn);
+                calculate_frame(&mut game, Event::KeyDrop);
+                while pieces(&game) < 4 && active(&game).is_some() {
+                    calculate_frame(&mut game, Event::Timeout);
+                }
+                while active(&game).is_none() {
+                    calculate_frame(&mut game, Event::Timeout);
+                }
+            }
+            let events = drain_events(&mut game);
+            assert_eq!(events.iter().filter(|e| matches!(e, GameEvent::Rotated{orientation: Orientation::Right, ..})).count(), 3);
+            assert_eq!(events.iter().filter(|e| matches!(e, GameEvent::Locked{col: 0, row: 3, ..})).count(), 3);
+            assert_eq!(events.iter().filter(|e| matches!(e, GameEvent::Spawned{..})).count(), 3);
+            let clears: Vec<&GameEvent> = events.iter().filter(|e| matches!(e, GameEvent::LinesCleared{..})).collect();
+            assert_eq!(clears.len(), 3);
+            assert_eq!(*clears[0], GameEvent::LinesCleared{rows: vec![3, 2, 1, 0], clear: ClearType::Tetris, chain: 1});
+            assert_eq!(events.contains(&GameEvent::LevelUp{level: 1}), true);
+
+            struct Counter(usize);
+            impl GameObserver for Counter {
+                fn notify(self: &mut Self, event: &GameEvent) {
+                    if let GameEvent::GameOver{reason: GameOverReason::UserQuit} = event {
+                        self.0 += 1;
+                    }
+                }
+            }
+            let mut counter = Counter(0);
+            calculate_frame(&mut game, Event::KeyExit);
+            calculate_frame(&mut game, Event::KeyExit);
+            dispatch_events(&mut game, &mut counter);
+            assert_eq!(counter.0, 1);
+        }
+
+        #[test]
+        fn ultra_time_up() {
+            let mut game = new_game(Config{mode: Mode::Ultra{seconds: 2}, ..config()}).unwrap();
+            for _ in 1..2 * FRAME_RATE {
+                calculate_frame(&mut game, Event::Timeout);
+            }
+            assert_eq!(is_finished(&game), false);
+            calculate_frame(&mut game, Event::Timeout);
+            assert_eq!(game_over_reason(&game), Some(GameOverReason::TimeUp));
+            assert_eq!(matches!(final_score(&game), Score::Ultra{score: 0, lines_cleared: 0}), true);
+            /* huge limits don't overflow */
+            assert_eq!(Mode::Ultra{seconds: u32::MAX}.time_limit(), Some(u32::MAX));
+        }
+
+        #[test]
+        fn cheese_race() {
+            use crate::headless::{Simulator, Action};
+            /* single cell piece fills the hole of the top garbage row */
+            let set: &'static crate::piece_set::PieceSet =
+                Box::leak(Box::new(crate::piece_set::PieceSet::parse("dot", "piece D 1 5 5\n#\n").unwrap()));
+            let dimensions = playfield::Dimensions::new(4, 8);
+            let config = Config{mode: Mode::Cheese{rows: 6}, rotation_system: set, dimensions: dimensions, ..config()};
+            let mut simulator = Simulator::new(config).unwrap();
+            let garbage_rows = |game: &Game| (0..dimensions.total_height()).filter(|row| game.playfield.playfield().garbage_row(*row)).collect::<Vec<i8>>();
+            /* half of the playfield is garbage */
+            assert_eq!(garbage_rows(simulator.game()), vec![0, 1, 2, 3]);
+
+            for cleared in 1..=6 {
+                let game = simulator.game();
+                let top = *garbage_rows(game).last().unwrap();
+                let holes: Vec<i8> = (0..4).filter(|col| cell(game, &playfield::Coords{row: top, col: *col}) == Shape::NoShape).collect();
+                assert_eq!(holes.len(), 1);
+                let result = simulator.step(&Action::Place{col: holes[0], orientation: Orientation::Spawn, hold: false});
+                assert_eq!(result.lines_cleared, 1);
+                assert_eq!(garbage_rows(simulator.game()).len(), std::cmp::min(4, 6 - cleared));
+            }
+            let game = simulator.game();
+            assert_eq!(game_over_reason(game), Some(GameOverReason::GoalReached));
+            assert_eq!(matches!(final_score(game), Score::Cheese{pieces: 6, goal: 6, ..}), true);
+        }
+
+        #[test]
+        fn survival_garbage_rises() {
+            assert_eq!(Mode::Survival.rise_interval(0), Some(8 * FRAME_RATE));
+            assert_eq!(Mode::Survival.rise_interval(4), Some(6 * FRAME_RATE));
+            assert_eq!(Mode::Survival.rise_interval(29), Some(FRAME_RATE));
+            assert_eq!(Mode::Marathon.rise_interval(0), None);
+
+            let mut game = new_game(Config{mode: Mode::Survival, ..config()}).unwrap();
+            let garbage_rows = |game: &Game| (0..playfield::HEIGHT).filter(|row| game.playfield.playfield().garbage_row(*row)).count();
+            for _ in 1..8 * FRAME_RATE {
+                calculate_frame(&mut game, Event::Timeout);
+            }
+            assert_eq!(garbage_rows(&game), 0);
+            calculate_frame(&mut game, Event::Timeout);
+            assert_eq!(garbage_rows(&game), 1);
+            for _ in 0..8 * FRAME_RATE {
+                calculate_frame(&mut game, Event::Timeout);
+            }
+            assert_eq!(garbage_rows(&game), 2);
+
+            calculate_frame(&mut game, Event::KeyExit);
+            assert_eq!(matches!(final_score(&game), Score::Survival{frames, level: 0, lines_cleared: 0} if frames >= 16 * FRAME_RATE), true);
+        }
+
+        #[test]
+        fn master_timings() {
+            let mut game = new_game(Config{mode: Mode::Master, ..config()}).unwrap();
+            /* next piece waits for ARE */
+            let mut frames = 0;
+            while active(&game).is_none() {
+                calculate_frame(&mut game, Event::Timeout);
+                frames += 1;
+            }
+            assert_eq!(frames, 25);
+            assert_eq!(game.score.master_level(), 1);
+
+            /* at 20G piece lands as soon as it appears and locks after lock delay */
+            game.score.restore_master_level(500);
+            calculate_frame(&mut game, Event::KeyDrop);
+            while active(&game).is_some() {
+                calculate_frame(&mut game, Event::Timeout);
+            }
+            while active(&game).is_none() {
+                calculate_frame(&mut game, Event::Timeout);
+            }
+            assert_eq!(game.score.master_level(), 501);
+            assert_eq!(active(&game).unwrap().coords.row, ghost(&game).unwrap().coords.row);
+            assert_eq!(state(&game), State::LockedPhase);
+            for _ in 1..30 {
+                calculate_frame(&mut game, Event::Timeout);
+            }
+            assert_eq!(pieces(&game), 2);
+            calculate_frame(&mut game, Event::Timeout);
+            assert_eq!(active(&game).is_none(), true);
+            assert_eq!(pieces(&game), 2);
+
+            /* only a line clear goes past the last level */
+            game.score.restore_master_level(998);
+            game.score.update(1, 0);
+            assert_eq!(game.score.goal_complete(), true);
+            assert_eq!(matches!(game.score.score(2), Score::Master{level: 999, lines_cleared: 1, ..}), true);
+        }
+
+        #[test]
+        fn zen_board_resets() {
+            let mut text = String::new();
+            for _ in 0..playfield::HEIGHT {
+                text += "GGGGGGGGG.\n";
+            }
+            let board = Board::parse(&text, &SRS).unwrap();
+            let mut game = new_game_with_playfield(Config{mode: Mode::Zen, level: 5, ..config()}, playfield::Playfield::new(board.storage)).unwrap();
+            subscribe(&mut game);
+            /* blocked piece empties the board and the next one comes */
+            calculate_frame(&mut game, Event::Timeout);
+            assert_eq!(is_finished(&game), false);
+            assert_eq!(board_resets(&game), 1);
+            assert_eq!(cell(&game, &playfield::Coords{row: 0, col: 0}), Shape::NoShape);
+            assert_eq!(active(&game).is_some(), true);
+            assert_eq!(drain_events(&mut game).contains(&GameEvent::BoardReset{resets: 1}), true);
+
+            add_garbage(&mut game, playfield::HEIGHT + playfield::BUFFER_HEIGHT, &[0]);
+            assert_eq!(is_finished(&game), false);
+            assert_eq!(board_resets(&game), 2);
+
+            /* gravity stays at the chosen level */
+            game.score.update(4, 0);
+            game.score.update(4, 0);
+            game.score.update(4, 0);
+            assert_eq!(level(&game), 5);
+            calculate_frame(&mut game, Event::KeyExit);
+            assert_eq!(game_over_reason(&game), Some(GameOverReason::UserQuit));
+            assert_eq!(matches!(final_score(&game), Score::Zen{lines_cleared: 12, ..}), true);
+        }
+
+        #[test]
+        fn user_quit() {
+            let mut game = new_game(config()).unwrap();
+            calculate_frame(&mut game, Event::KeyExit);
+            assert_eq!(game_over_reason(&game), Some(GameOverReason::UserQuit));
+        }
+    }
 }
@@ -1,6 +1,13 @@
+use crate::master::{CELL};
+
+#[derive(Clone)]
 pub struct Fall {
     pub frame_counter: i8,
-    lock_delay_triggered: bool,
+    pub lock_delay_triggered: bool,
+    /* master mode gravity: part of a cell fallen so far, in 1/256 */
+    pub gravity_fraction: u32,
+    /* master mode: frames spent on the ground */
+    pub lock_frames: u32,
 }
 
 pub const FRAME_RATE: u32 = 60;
@@ -34,9 +41,25 @@ impl Fall {
         }
     }
 
+    /* Cells to fall this frame with gravity given in 1/256 of a cell per frame */
+    pub fn gravity_drops(self: &mut Self, gravity: u32) -> u32 {
+        self.gravity_fraction += gravity;
+        let drops = self.gravity_fraction / CELL;
+        self.gravity_fraction %= CELL;
+        drops
+    }
+
+    /* Returns true when piece has been on the ground for lock delay frames */
+    pub fn lock_tick(self: &mut Self, lock_delay: u32) -> bool {
+        self.lock_frames += 1;
+        self.lock_frames >= lock_delay
+    }
+
     pub fn reset(self: &mut Self) {
         self.lock_delay_triggered = false;
         self.frame_counter = 0;
+        self.gravity_fraction = 0;
+        self.lock_frames = 0;
     }
 
     pub fn lock_delay(self: &mut Self) {
@@ -44,6 +67,6 @@ impl Fall {
     }
 
     pub fn new() -> Self {
-        Fall{frame_counter: 0, lock_delay_triggered: false}
+        Fall{frame_counter: 0, lock_delay_triggered: false, gravity_fraction: 0, lock_frames: 0}
     }
 }
@@ -1,8 +1,10 @@
 pub mod figures {
     use crate::playfield::Coords;
+    use crate::rotation::{RotationSystem, SRS};
 
-    #[derive(Clone, PartialEq, Debug)]
+    #[derive(Clone, PartialEq, Debug, Default)]
     pub enum Shape {
+        #[default]
         NoShape,
         OShape,
         IShape,
@@ -11,109 +13,168 @@ pub mod figures {
         LShape,
         SShape,
         ZShape,
+        /* filler cells pushed up from the bottom, never a falling piece */
+        Garbage,
+        /* piece from runtime loaded set, knows its own name and color */
+        Custom{index: u8, name: char, color: Color},
     }
 
-    impl Default for Shape {
-        fn default() -> Self { Shape::NoShape }
+    impl Shape {
+        /* Single letter used in text boards, '.' for empty cell */
+        pub fn name(self: &Self) -> char {
+            match self {
+                Shape::NoShape => '.',
+                Shape::OShape => 'O',
+                Shape::IShape => 'I',
+                Shape::TShape => 'T',
+                Shape::JShape => 'J',
+                Shape::LShape => 'L',
+                Shape::SShape => 'S',
+                Shape::ZShape => 'Z',
+                Shape::Garbage => 'G',
+                Shape::Custom{name, ..} => *name,
+            }
+        }
     }
 
-    pub const LAYOUT_WIDTH: i8 = 4;
-    pub const LAYOUT_HEIGHT: i8 = 4;
-    pub type Layout = [[u8; LAYOUT_WIDTH as usize]; LAYOUT_HEIGHT as usize];
+    /* Color components in 0..=5 range */
+    pub type Color = (u8, u8, u8);
 
-    #[derive(Clone)]
-    pub struct Tetrimino {
-        pub shape: Shape,
-        layout: Layout,
-        iter_row: i8,
-        iter_col: i8,
-    }
+    pub const TETROMINOES: [Shape; 7] = [Shape::OShape, Shape::IShape,
+                                         Shape::TShape, Shape::JShape,
+                                         Shape::LShape, Shape::SShape,
+                                         Shape::ZShape,];
 
-    fn rotate_special_i(layout: &mut Layout) {
-        /* simply transpose matrix */
-        for row in 0..4 {
-            for col in row..4 {
-                let temp = layout[row][col];
-                layout[row][col] = layout[col][row];
-                layout[col][row] = temp;
+    /* Layout is big enough to hold any pentomino */
+    pub const LAYOUT_WIDTH: i8 = 5;
+    pub const LAYOUT_HEIGHT: i8 = 5;
+    pub type Layout = [[u8; LAYOUT_WIDTH as usize]; LAYOUT_HEIGHT as usize];
+    pub type TetroLayout = [[u8; 4]; 4];
+    /* Bit per layout cell, bit number is layout column */
+    pub type RowMasks = [u8; LAYOUT_HEIGHT as usize];
+
+    fn row_masks(layout: &Layout) -> RowMasks {
+        let mut masks: RowMasks = Default::default();
+        for (mask, cells) in masks.iter_mut().zip(layout.iter()) {
+            for (col, cell) in cells.iter().enumerate() {
+                if *cell != 0 {
+                    *mask |= 1 << col;
+                }
             }
         }
+        masks
     }
 
-    fn rotate_special_s(layout: &mut Layout) {
-        /* s shape switches between two states */
-        let original_layout = Tetrimino::new(Shape::SShape).layout;
-        let turned_layout = [[0, 1, 0, 0], [0, 1, 1, 0], [0, 0, 1, 0], [0, 0, 0, 0]];
-        if *layout == original_layout {
-            *layout = turned_layout;
-        } else {
-            *layout = original_layout;
+    /* Put tetromino layout into top left corner of a full sized one */
+    pub fn widen(tetro_layout: &TetroLayout) -> Layout {
+        let mut layout: Layout = Default::default();
+        for (row, cells) in tetro_layout.iter().enumerate() {
+            layout[row][..cells.len()].copy_from_slice(cells);
         }
+        layout
     }
 
-    fn rotate_special_z(layout: &mut Layout) {
-        /* z shape switches between two states */
-        let original_layout = Tetrimino::new(Shape::ZShape).layout;
-        let turned_layout = [[0, 0, 1, 0], [0, 1, 1, 0], [0, 1, 0, 0], [0, 0, 0, 0]];
-        if *layout == original_layout {
-            *layout = turned_layout;
-        } else {
-            *layout = original_layout;
+    /* SRS orientation states, named after the direction the piece faces */
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum Orientation {
+        Spawn,
+        Right,
+        Reverse,
+        Left,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum Rotation {
+        Clockwise,
+        CounterClockwise,
+        Flip,
+    }
+
+    impl Orientation {
+        pub fn clockwise(self: Self) -> Orientation {
+            match self {
+                Orientation::Spawn => Orientation::Right,
+                Orientation::Right => Orientation::Reverse,
+                Orientation::Reverse => Orientation::Left,
+                Orientation::Left => Orientation::Spawn,
+            }
+        }
+
+        pub fn counter_clockwise(self: Self) -> Orientation {
+            match self {
+                Orientation::Spawn => Orientation::Left,
+                Orientation::Right => Orientation::Spawn,
+                Orientation::Reverse => Orientation::Right,
+                Orientation::Left => Orientation::Reverse,
+            }
+        }
+
+        pub fn flip(self: Self) -> Orientation {
+            self.clockwise().clockwise()
+        }
+
+        pub fn turn(self: Self, rotation: Rotation) -> Orientation {
+            match rotation {
+                Rotation::Clockwise => self.clockwise(),
+                Rotation::CounterClockwise => self.counter_clockwise(),
+                Rotation::Flip => self.flip(),
+            }
         }
     }
 
-    fn rotate_ordinary(layout: &mut Layout) {
-        /* rotate corner pieces clockwise
-         *   0123
-         * 0 *-*-
-         * 1 ----
-         * 2 *-*-
-         * 3 ----
-         */
-        let temp = layout[0][2];
-        layout[0][2] = layout[0][0];
-        layout[0][0] = layout[2][0];
-        layout[2][0] = layout[2][2];
-        layout[2][2] = temp;
-        /* rotate in-between corner pieces clockwise
-         *   0123
-         * 0 -*--
-         * 1 *-*-
-         * 2 -*--
-         * 3 ----
-         */
-        let temp = layout[0][1];
-        layout[0][1] = layout[1][0];
-        layout[1][0] = layout[2][1];
-        layout[2][1] = layout[1][2];
-        layout[1][2] = temp;
+    #[derive(Clone)]
+    pub struct Tetrimino {
+        pub shape: Shape,
+        orientation: Orientation,
+        layout: Layout,
+        masks: RowMasks,
+        system: &'static dyn RotationSystem,
+        iter_row: i8,
+        iter_col: i8,
     }
 
+    pub fn turn(tetromino: &mut Tetrimino, rotation: Rotation) {
+        tetromino.orientation = tetromino.orientation.turn(rotation);
+        tetromino.layout = tetromino.system.layout(&tetromino.shape, tetromino.orientation);
+        tetromino.masks = row_masks(&tetromino.layout);
+    }
+
+    /* Shortcut for a clockwise turn */
     pub fn rotate(tetromino: &mut Tetrimino) {
-        match tetromino.shape {
-            /* o doesn't need any rotation */
-            Shape::OShape => (),
-            /* i, s, z are not "just rotations" */
-            Shape::IShape => rotate_special_i(&mut tetromino.layout),
-            Shape::SShape => rotate_special_s(&mut tetromino.layout),
-            Shape::ZShape => rotate_special_z(&mut tetromino.layout),
-            _ => rotate_ordinary(&mut tetromino.layout),
-        }
+        turn(tetromino, Rotation::Clockwise);
     }
 
     impl Tetrimino {
         pub fn new(shape: Shape) -> Tetrimino {
-            let layout = match shape {
-                Shape::OShape => [[0, 0, 0, 0], [0, 1, 1, 0], [0, 1, 1, 0], [0, 0, 0, 0]],
-                Shape::IShape => [[0, 0, 0, 0], [0, 0, 0, 0], [1, 1, 1, 1], [0, 0, 0, 0]],
-                Shape::TShape => [[0, 1, 0, 0], [1, 1, 1, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
-                Shape::JShape => [[1, 0, 0, 0], [1, 1, 1, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
-                Shape::LShape => [[0, 0, 1, 0], [1, 1, 1, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
-                Shape::SShape => [[0, 0, 0, 0], [0, 1, 1, 0], [1, 1, 0, 0], [0, 0, 0, 0]],
-                Shape::ZShape => [[0, 0, 0, 0], [1, 1, 0, 0], [0, 1, 1, 0], [0, 0, 0, 0]],
-                Shape::NoShape => [[0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
-            };
-            Tetrimino {shape: shape, layout: layout, iter_row: 0, iter_col: 0}
+            Tetrimino::with_system(shape, &SRS)
+        }
+
+        pub fn with_system(shape: Shape, system: &'static dyn RotationSystem) -> Tetrimino {
+            let orientation = system.spawn_orientation(&shape);
+            let layout = system.layout(&shape, orientation);
+            Tetrimino {shape: shape, orientation: orientation, layout: layout, masks: row_masks(&layout),
+                       system: system, iter_row: 0, iter_col: 0}
+        }
+
+        /* Occupied cells of every layout row packed into bits */
+        pub fn row_masks(self: &Self) -> &RowMasks {
+            &self.masks
+        }
+
+        pub fn orientation(self: &Self) -> Orientation {
+            self.orientation
+        }
+
+        /* Offsets to try in order when turning from current orientation */
+        pub fn kicks(self: &Self, rotation: Rotation) -> &'static [Coords] {
+            self.system.kicks(&self.shape, self.orientation, rotation)
+        }
+
+        /* Lowest layout row occupied by the shape */
+        pub fn bottom_row(self: &Self) -> i8 {
+            (0..LAYOUT_HEIGHT).rev()
+                .find(|row| self.layout[*row as usize].iter().any(|c| *c != 0))
+                .unwrap_or(0)
         }
 
         pub fn shape_at(self: &Self, coords: &Coords) -> Shape {
@@ -128,17 +189,17 @@ pub mod figures {
         type Item = (Coords, Shape);
 
         fn next(&mut self) -> Option<Self::Item> {
+            if self.iter_row == LAYOUT_HEIGHT {
+                self.iter_row = 0;
+                self.iter_col = 0;
+                return None;
+            }
             let coords = Coords{row: self.iter_row, col: self.iter_col};
             self.iter_col += 1;
             if self.iter_col == LAYOUT_WIDTH {
                 self.iter_col = 0;
                 self.iter_row += 1;
             }
-            if self.iter_row == LAYOUT_HEIGHT {
-                self.iter_row = 0;
-                self.iter_col = 0;
-                return None;
-            }
 
             Some((coords, self.shape_at(&coords)))
         }
@@ -150,7 +211,8 @@ mod tests {
     use super::figures::*;
     use crate::playfield::Coords;
 
-    fn assert_shape(tetro: &Tetrimino, binary_array: &Layout) {
+    fn assert_shape(tetro: &Tetrimino, binary_array: &TetroLayout) {
+        let binary_array = widen(binary_array);
         for row in 0..LAYOUT_HEIGHT {
             for col in 0..LAYOUT_WIDTH {
                 let coords = Coords{row: row, col: col};
@@ -191,7 +253,7 @@ mod tests {
      #[test]
     fn rotate_i_shape() {
         let mut f = Tetrimino::new(Shape::IShape);
-        /* i shape switches between two states */
+        /* i shape goes through all four cells of its 4x4 box */
         /* 90 degrees */
         rotate(&mut f);
         assert_shape(&f, &[[0, 0, 1, 0], [0, 0, 1, 0], [0, 0, 1, 0], [0, 0, 1, 0]]);
@@ -200,10 +262,10 @@ mod tests {
         assert_shape(&f, &[[0, 0, 0, 0], [0, 0, 0, 0], [1, 1, 1, 1], [0, 0, 0, 0]]);
         /* 270 degrees */
         rotate(&mut f);
-        assert_shape(&f, &[[0, 0, 1, 0], [0, 0, 1, 0], [0, 0, 1, 0], [0, 0, 1, 0]]);
+        assert_shape(&f, &[[0, 1, 0, 0], [0, 1, 0, 0], [0, 1, 0, 0], [0, 1, 0, 0]]);
         /* 360 degrees */
         rotate(&mut f);
-        assert_shape(&f, &[[0, 0, 0, 0], [0, 0, 0, 0], [1, 1, 1, 1], [0, 0, 0, 0]]);
+        assert_shape(&f, &[[0, 0, 0, 0], [1, 1, 1, 1], [0, 0, 0, 0], [0, 0, 0, 0]]);
     }
 
     #[test]
@@ -268,10 +330,10 @@ mod tests {
         assert_shape(&f, &[[0, 0, 0, 0], [0, 1, 1, 0], [1, 1, 0, 0], [0, 0, 0, 0]]);
         /* 270 degrees */
         rotate(&mut f);
-        assert_shape(&f, &[[0, 1, 0, 0], [0, 1, 1, 0], [0, 0, 1, 0], [0, 0, 0, 0]]);
+        assert_shape(&f, &[[1, 0, 0, 0], [1, 1, 0, 0], [0, 1, 0, 0], [0, 0, 0, 0]]);
         /* 360 degrees */
         rotate(&mut f);
-        assert_shape(&f, &[[0, 0, 0, 0], [0, 1, 1, 0], [1, 1, 0, 0], [0, 0, 0, 0]]);
+        assert_shape(&f, &[[0, 1, 1, 0], [1, 1, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]]);
     }
 
     #[test]
@@ -285,21 +347,105 @@ mod tests {
         assert_shape(&f, &[[0, 0, 0, 0], [1, 1, 0, 0], [0, 1, 1, 0], [0, 0, 0, 0]]);
         /* 270 degrees */
         rotate(&mut f);
-        assert_shape(&f, &[[0, 0, 1, 0], [0, 1, 1, 0], [0, 1, 0, 0], [0, 0, 0, 0]]);
+        assert_shape(&f, &[[0, 1, 0, 0], [1, 1, 0, 0], [1, 0, 0, 0], [0, 0, 0, 0]]);
         /* 360 degrees */
         rotate(&mut f);
-        assert_shape(&f, &[[0, 0, 0, 0], [1, 1, 0, 0], [0, 1, 1, 0], [0, 0, 0, 0]]);
+        assert_shape(&f, &[[1, 1, 0, 0], [0, 1, 1, 0], [0, 0, 0, 0], [0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn orientation_tracks_rotation() {
+        let mut f = Tetrimino::new(Shape::TShape);
+        assert_eq!(f.orientation(), Orientation::Spawn);
+        rotate(&mut f);
+        assert_eq!(f.orientation(), Orientation::Right);
+        rotate(&mut f);
+        assert_eq!(f.orientation(), Orientation::Reverse);
+        rotate(&mut f);
+        assert_eq!(f.orientation(), Orientation::Left);
+        rotate(&mut f);
+        assert_eq!(f.orientation(), Orientation::Spawn);
+    }
+
+    #[test]
+    fn counter_clockwise_undoes_clockwise() {
+        for shape in [Shape::IShape, Shape::TShape, Shape::JShape, Shape::LShape, Shape::SShape, Shape::ZShape].iter() {
+            let mut f = Tetrimino::new(shape.clone());
+            for _ in 0..4 {
+                let before: Vec<(Coords, Shape)> = f.clone().collect();
+                rotate(&mut f);
+                turn(&mut f, Rotation::CounterClockwise);
+                let after: Vec<(Coords, Shape)> = f.clone().collect();
+                for (b, a) in before.iter().zip(after.iter()) {
+                    assert_eq!(b.1, a.1, "{:?} (row: {} col: {})", shape, b.0.row, b.0.col);
+                }
+                rotate(&mut f);
+            }
+        }
+    }
+
+    #[test]
+    fn rotate_t_shape_ccw() {
+        let mut f = Tetrimino::new(Shape::TShape);
+        /* 90 degrees counter clockwise */
+        turn(&mut f, Rotation::CounterClockwise);
+        assert_shape(&f, &[[0, 1, 0, 0], [1, 1, 0, 0], [0, 1, 0, 0], [0, 0, 0, 0]]);
+        /* 180 degrees */
+        turn(&mut f, Rotation::CounterClockwise);
+        assert_shape(&f, &[[0, 0, 0, 0], [1, 1, 1, 0], [0, 1, 0, 0], [0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn flip_l_shape() {
+        let mut f = Tetrimino::new(Shape::LShape);
+        turn(&mut f, Rotation::Flip);
+        assert_eq!(f.orientation(), Orientation::Reverse);
+        assert_shape(&f, &[[0, 0, 0, 0], [1, 1, 1, 0], [1, 0, 0, 0], [0, 0, 0, 0]]);
+        turn(&mut f, Rotation::Flip);
+        assert_eq!(f.orientation(), Orientation::Spawn);
+        assert_shape(&f, &[[0, 0, 1, 0], [1, 1, 1, 0], [0, 0, 0, 0], [0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn with_system_uses_spawn_orientation() {
+        let f = Tetrimino::with_system(Shape::TShape, &crate::rotation::ARS);
+        assert_eq!(f.orientation(), Orientation::Reverse);
+        assert_eq!(f.bottom_row(), 2);
+        assert_shape(&f, &[[0, 0, 0, 0], [1, 1, 1, 0], [0, 1, 0, 0], [0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn row_masks_follow_layout() {
+        let mut f = Tetrimino::new(Shape::LShape);
+        assert_eq!(f.row_masks(), &[0b100, 0b111, 0, 0, 0]);
+        rotate(&mut f);
+        assert_eq!(f.row_masks(), &[0b010, 0b010, 0b110, 0, 0]);
+    }
+
+    #[test]
+    fn bottom_row_of_spawned_shapes() {
+        assert_eq!(Tetrimino::new(Shape::TShape).bottom_row(), 1);
+        assert_eq!(Tetrimino::new(Shape::IShape).bottom_row(), 1);
+        assert_eq!(Tetrimino::new(Shape::OShape).bottom_row(), 2);
     }
 
     #[test]
     fn iterator_interface() {
         let f = Tetrimino::new(Shape::ZShape);
-        let result = [[Shape::NoShape, Shape::NoShape, Shape::NoShape, Shape::NoShape],
-                      [Shape::ZShape, Shape::ZShape, Shape::NoShape, Shape::NoShape],
+        let result = [[Shape::ZShape, Shape::ZShape, Shape::NoShape, Shape::NoShape],
                       [Shape::NoShape, Shape::ZShape, Shape::ZShape, Shape::NoShape],
+                      [Shape::NoShape, Shape::NoShape, Shape::NoShape, Shape::NoShape],
                       [Shape::NoShape, Shape::NoShape, Shape::NoShape, Shape::NoShape]];
+        let mut count = 0;
         for (coords, shape) in f {
-            assert_eq!(shape, result[coords.row as usize][coords.col as usize]);
+            if coords.row < 4 && coords.col < 4 {
+                assert_eq!(shape, result[coords.row as usize][coords.col as usize]);
+            } else {
+                assert_eq!(shape, Shape::NoShape);
+            }
+            count += 1;
         }
+        /* every cell of layout is visited exactly once */
+        assert_eq!(count, LAYOUT_WIDTH * LAYOUT_HEIGHT);
     }
 }
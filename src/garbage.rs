@@ -0,0 +1,67 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::{StdRng};
+
+/* Keeps garbage holes away from piece bag randomness, so adding garbage
+ * doesn't change the pieces dealt for the same seed */
+const SEED_SALT: u64 = 0x6761_7262_6167_6521;
+
+/* Deals hole columns of garbage rows, seeded like the piece bag */
+#[derive(Clone)]
+pub struct Garbage {
+    rng: StdRng,
+    seed: u64,
+    /* rows dealt so far */
+    rows: u32,
+    last_hole: Option<i8>,
+}
+
+impl Garbage {
+    /* Hole for the next row, never in the same column as the previous one
+     * so the rows can't be cleared by a single well */
+    pub fn next_hole(self: &mut Self, width: i8) -> i8 {
+        self.rows += 1;
+        let hole = match self.last_hole {
+            Some(last) if width > 1 => {
+                let hole = self.rng.gen_range(0, width - 1);
+                if hole >= last { hole + 1 } else { hole }
+            },
+            _ => self.rng.gen_range(0, width),
+        };
+        self.last_hole = Some(hole);
+        hole
+    }
+
+    pub fn rows(self: &Self) -> u32 {
+        self.rows
+    }
+
+    /* Continue saved game, holes are dealt again to get rng to the same point */
+    pub fn restore(self: &mut Self, rows: u32, width: i8) {
+        *self = Garbage::new(self.seed);
+        for _ in 0..rows {
+            self.next_hole(width);
+        }
+    }
+
+    pub fn new(seed: u64) -> Self {
+        Garbage{rng: StdRng::seed_from_u64(seed ^ SEED_SALT), seed: seed, rows: 0, last_hole: None}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messy_holes() {
+        let mut garbage = Garbage::new(5);
+        let holes: Vec<i8> = (0..100).map(|_| garbage.next_hole(10)).collect();
+        assert_eq!(holes.iter().all(|h| (0..10).contains(h)), true);
+        assert_eq!(holes.windows(2).any(|w| w[0] == w[1]), false);
+        assert_eq!(garbage.rows(), 100);
+
+        let mut restored = Garbage::new(5);
+        restored.restore(60, 10);
+        assert_eq!((60..100).map(|_| restored.next_hole(10)).collect::<Vec<i8>>(), holes[60..].to_vec());
+    }
+}
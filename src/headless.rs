@@ -0,0 +1,242 @@
+use crate::engine::engine::{self, Config, ConfigError, Event, Game, GameEvent, State, ClearType};
+use crate::playfield::{Storage, FieldTetrimino};
+use crate::figures::figures::{Shape, Orientation};
+use crate::fall::{FRAME_RATE};
+
+/* Enough for a piece to fall through the tallest playfield at level 0
+ * and lock, inputs that never lock the piece stop here */
+const MAX_STEP_FRAMES: u32 = 60 * FRAME_RATE;
+
+pub enum Action {
+    /* Hold if asked, turn clockwise into orientation, shift layout top left
+     * corner to the column and hard drop */
+    Place{col: i8, orientation: Orientation, hold: bool},
+    /* Given inputs go without any time between them, gravity does the rest */
+    Inputs(Vec<Event>),
+}
+
+/* Game state after a step, the same things engine query API gives */
+pub struct Observation {
+    pub board: Storage,
+    pub active: Option<FieldTetrimino>,
+    pub ghost: Option<FieldTetrimino>,
+    pub next: Vec<Shape>,
+    pub hold: Option<Shape>,
+    pub state: State,
+    pub level: i8,
+    pub lines: u32,
+    pub pieces: u32,
+    pub frames: u32,
+}
+
+/* Step of clear chain, the same as GameEvent::LinesCleared */
+#[derive(Clone, PartialEq, Debug)]
+pub struct LineClear {
+    /* top down, as they were before removal */
+    pub rows: Vec<i8>,
+    pub clear: ClearType,
+    pub chain: u8,
+}
+
+pub struct StepResult {
+    pub observation: Observation,
+    /* lines cleared by the locked piece, cascade chains included */
+    pub lines_cleared: u32,
+    /* every clear made by the locked piece, a cascade makes several */
+    pub clears: Vec<LineClear>,
+    /* false if Place action couldn't reach target column or orientation */
+    pub reached: bool,
+    /* timeouts the engine was given */
+    pub frames: u32,
+    pub finished: bool,
+}
+
+/* Drives engine without a view or real time, a piece per step */
+pub struct Simulator {
+    game: Game,
+}
+
+impl Simulator {
+    pub fn new(config: Config) -> Result<Self, ConfigError> {
+        Ok(Simulator::with_game(engine::new_game(config)?))
+    }
+
+    /* Start from a prepared game, e.g. a loaded one */
+    pub fn with_game(mut game: Game) -> Self {
+        /* clears are taken from game events */
+        engine::subscribe(&mut game);
+        let mut simulator = Simulator{game: game};
+        simulator.spawn();
+        simulator
+    }
+
+    /* Run until there is a piece to control */
+    fn spawn(self: &mut Self) -> u32 {
+        let mut frames = 0;
+        while engine::active(&self.game).is_none() && !engine::is_finished(&self.game) && frames < MAX_STEP_FRAMES {
+            engine::calculate_frame(&mut self.game, Event::Timeout);
+            frames += 1;
+        }
+        frames
+    }
+
+    fn active(self: &Self) -> Option<&FieldTetrimino> {
+        engine::active(&self.game)
+    }
+
+    /* Returns false if piece got stuck on the way */
+    fn place(self: &mut Self, col: i8, orientation: Orientation, hold: bool) -> bool {
+        if hold {
+            engine::calculate_frame(&mut self.game, Event::KeyHold);
+        }
+        for _ in 0..4 {
+            match self.active() {
+                Some(active) if active.tetro.orientation() != orientation => {
+                    engine::calculate_frame(&mut self.game, Event::KeyTurn);
+                },
+                _ => break,
+            }
+        }
+        let mut last_col = None;
+        while let Some(active) = self.active() {
+            let current = active.coords.col;
+            if current == col || last_col == Some(current) {
+                break;
+            }
+            last_col = Some(current);
+            engine::calculate_frame(&mut self.game, if current < col { Event::KeyRight } else { Event::KeyLeft });
+        }
+        let reached = match self.active() {
+            Some(active) => active.coords.col == col && active.tetro.orientation() == orientation,
+            None => false,
+        };
+        engine::calculate_frame(&mut self.game, Event::KeyDrop);
+        reached
+    }
+
+    /* Play one piece: apply action and run until next piece appears or game ends */
+    pub fn step(self: &mut Self, action: &Action) -> StepResult {
+        let pieces = engine::pieces(&self.game);
+        let lines = engine::lines(&self.game);
+        engine::drain_events(&mut self.game);
+        let reached = match action {
+            Action::Place{col, orientation, hold} => self.place(*col, *orientation, *hold),
+            Action::Inputs(events) => {
+                for event in events {
+                    engine::calculate_frame(&mut self.game, event.clone());
+                }
+                true
+            },
+        };
+
+        let mut frames = 0;
+        while engine::pieces(&self.game) == pieces && !engine::is_finished(&self.game) && frames < MAX_STEP_FRAMES {
+            engine::calculate_frame(&mut self.game, Event::Timeout);
+            frames += 1;
+        }
+        frames += self.spawn();
+        let clears = engine::drain_events(&mut self.game).into_iter().filter_map(|event| match event {
+            GameEvent::LinesCleared{rows, clear, chain} => Some(LineClear{rows: rows, clear: clear, chain: chain}),
+            _ => None,
+        }).collect();
+
+        StepResult{
+            observation: self.observe(),
+            lines_cleared: engine::lines(&self.game) - lines,
+            clears: clears,
+            reached: reached,
+            frames: frames,
+            finished: engine::is_finished(&self.game),
+        }
+    }
+
+    pub fn observe(self: &Self) -> Observation {
+        let game = &self.game;
+        Observation{
+            board: engine::playfield(game).storage().clone(),
+            active: engine::active(game).cloned(),
+            ghost: engine::ghost(game),
+            next: engine::next_queue(game).to_vec(),
+            hold: engine::hold(game),
+            state: engine::state(game),
+            level: engine::level(game),
+            lines: engine::lines(game),
+            pieces: engine::pieces(game),
+            frames: engine::frames(game),
+        }
+    }
+
+    pub fn game(self: &Self) -> &Game {
+        &self.game
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::engine::{Mode};
+    use crate::playfield::{Dimensions, ClearMode, Coords};
+    use crate::piece_set::{PieceSet};
+    use crate::rotation::{RotationSystem, SRS};
+    use crate::figures::figures::{rotate};
+
+    fn config(rotation_system: &'static dyn RotationSystem, width: i8) -> Config {
+        Config{no_ghost: false, level: 0, next_queue_size: 2, mode: Mode::Marathon, rotation_system: rotation_system,
+               dimensions: Dimensions::new(width, 20), clear_mode: ClearMode::Naive, seed: 1}
+    }
+
+    #[test]
+    fn every_flat_i_clears_a_line() {
+        let set: &'static PieceSet = Box::leak(Box::new(PieceSet::parse("i", "piece I 1 5 5\n####\n").unwrap()));
+        let mut simulator = Simulator::new(config(set, 4)).unwrap();
+        for piece in 1..=5 {
+            let result = simulator.step(&Action::Place{col: 0, orientation: Orientation::Spawn, hold: false});
+            assert_eq!(result.reached, true);
+            assert_eq!(result.lines_cleared, 1);
+            assert_eq!(result.clears, vec![LineClear{rows: vec![0], clear: ClearType::Single, chain: 1}]);
+            assert_eq!(result.finished, false);
+            assert_eq!(result.observation.lines, piece);
+            assert_eq!(result.observation.active.is_some(), true);
+        }
+        /* standing piece doesn't fit into the corner */
+        let result = simulator.step(&Action::Place{col: -5, orientation: Orientation::Right, hold: false});
+        assert_eq!(result.reached, false);
+        assert_eq!(result.lines_cleared, 0);
+        assert_eq!(result.clears, vec![]);
+    }
+
+    #[test]
+    fn place_lands_on_target() {
+        let mut simulator = Simulator::new(config(&SRS, 10)).unwrap();
+        let mut expected = simulator.observe().active.unwrap();
+        let result = simulator.step(&Action::Place{col: 6, orientation: Orientation::Left, hold: false});
+        assert_eq!(result.reached, true);
+        assert_eq!(result.observation.pieces, 2);
+
+        while expected.tetro.orientation() != Orientation::Left {
+            rotate(&mut expected.tetro);
+        }
+        /* lowest cell of the piece ends up on the floor */
+        let bottom_row = expected.tetro.bottom_row();
+        for (coords, shape) in expected.tetro.clone() {
+            let field = Coords{row: bottom_row - coords.row, col: 6 + coords.col};
+            if shape != Shape::NoShape {
+                assert_eq!(*result.observation.board.cell(&field), shape);
+            }
+        }
+    }
+
+    #[test]
+    fn inputs_and_hold() {
+        let mut simulator = Simulator::new(config(&SRS, 10)).unwrap();
+        let first = simulator.observe().active.unwrap().tetro.shape;
+        let next = simulator.observe().next[0].clone();
+        /* no drop, gravity locks the piece */
+        let result = simulator.step(&Action::Inputs(vec![Event::KeyHold, Event::KeyLeft]));
+        assert_eq!(result.frames > 60, true);
+        assert_eq!(result.observation.pieces, 2);
+        /* held piece comes next */
+        assert_eq!(result.observation.active.as_ref().unwrap().tetro.shape, first);
+        assert_eq!((0..10).any(|col| *result.observation.board.cell(&Coords{row: 0, col: col}) == next), true);
+    }
+}
@@ -1,3 +1,6 @@
+#![allow(clippy::needless_arbitrary_self_type, clippy::redundant_field_names,
+         clippy::module_inception, clippy::new_without_default,
+         clippy::bool_assert_comparison)]
 pub mod figures;
 pub mod playfield;
 pub mod view;
@@ -10,4 +13,14 @@ pub mod static_ctrl;
 pub mod fall;
 pub mod playtime_ctrl;
 pub mod endgame_ctrl;
+pub mod pause_ctrl;
 pub mod tlv;
+pub mod rotation;
+pub mod piece_set;
+pub mod board;
+pub mod replay;
+pub mod replay_player;
+pub mod headless;
+pub mod autoplay;
+pub mod garbage;
+pub mod master;
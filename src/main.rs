@@ -1,7 +1,14 @@
+#![allow(clippy::needless_arbitrary_self_type, clippy::redundant_field_names,
+         clippy::module_inception, clippy::new_without_default)]
 use tetrust::engine::engine;
 use tetrust::view;
 use tetrust::playfield;
 use tetrust::fall::FRAME_RATE;
+use tetrust::rotation;
+use tetrust::replay::Replay;
+use tetrust::replay_player::ReplayPlayer;
+use tetrust::piece_set;
+use tetrust::autoplay::AutoPlayer;
 use std::thread;
 use std::time::Duration;
 use std::sync::mpsc;
@@ -9,29 +16,11 @@ use std::io::{stdin, stdout, Write};
 use termion::event::Key;
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
-use clap::{App, value_t};
+use clap::{App, ArgMatches, SubCommand, value_t};
 
-fn do_game(no_ghost: bool, level: u8, next_queue_size: u8, mode: engine::Mode) {
-    let playfield = playfield::Playfield::new(Default::default());
-    let config = engine::Config{no_ghost: no_ghost, level: level, next_queue_size: next_queue_size, mode: mode};
-    let mut game = engine::new_game(config, playfield);
-
-    let (timer_tx, rx) = mpsc::channel();
-    let keyboard_tx = timer_tx.clone();
-
-    thread::spawn(move || {
-        /* timeout generator */
-        const FRAME_INTERVAL: u64 = 1000000 / FRAME_RATE as u64;
-
-        loop {
-            let _ = timer_tx.send(engine::Event::Timeout);
-            thread::sleep(Duration::from_micros(FRAME_INTERVAL));
-        }
-    });
-
-    let mut stdout = stdout().into_raw_mode().unwrap();
-    write!(stdout, "{}{}", termion::cursor::Hide, termion::clear::All).unwrap();
+const FRAME_INTERVAL: u64 = 1000000 / FRAME_RATE as u64;
 
+fn spawn_keyboard(keyboard_tx: mpsc::Sender<engine::Event>) {
     thread::spawn(move || {
         let stdin = stdin();
 
@@ -40,42 +29,232 @@ fn do_game(no_ghost: bool, level: u8, next_queue_size: u8, mode: engine::Mode) {
                 Key::Left => {let _ = keyboard_tx.send(engine::Event::KeyLeft);},
                 Key::Right => {let _ = keyboard_tx.send(engine::Event::KeyRight);},
                 Key::Down => {let _ = keyboard_tx.send(engine::Event::KeyDown);},
-                Key::Up => {let _ = keyboard_tx.send(engine::Event::KeyTurn);},
+                Key::Up | Key::Char('x') => {let _ = keyboard_tx.send(engine::Event::KeyTurn);},
+                Key::Char('z') => {let _ = keyboard_tx.send(engine::Event::KeyTurnCcw);},
+                Key::Char('a') => {let _ = keyboard_tx.send(engine::Event::KeyFlip);},
                 Key::Char(' ') => {let _ = keyboard_tx.send(engine::Event::KeyDrop);},
                 Key::Char('h') => {let _ = keyboard_tx.send(engine::Event::KeyHold);},
+                Key::Char('p') => {let _ = keyboard_tx.send(engine::Event::KeyPause);},
                 Key::Char('q') | Key::Ctrl('z') | Key::Ctrl('c') => {let _ = keyboard_tx.send(engine::Event::KeyExit);},
                 _ => { /* do nothing */ }
             }
         }
     });
+}
+
+fn show_result(game: &engine::Game, seed: u64) {
+    let reason = engine::game_over_reason(game).unwrap_or(engine::GameOverReason::UserQuit);
+    print!("Final score: {:?} ({}) seed: {}\n\r", engine::final_score(game), reason, seed);
+    if engine::board_resets(game) > 0 {
+        print!("Board resets: {}\n\r", engine::board_resets(game));
+    }
+}
+
+/* Quitting saves the game here, --resume without a file loads it */
+fn default_save_path() -> String {
+    match std::env::var("HOME") {
+        Ok(home) => format!("{}/.tetrust.save", home),
+        Err(_) => ".tetrust.save".to_string(),
+    }
+}
+
+/* Backups are numbered, so an earlier one is never overwritten */
+fn backup_path(path: &str) -> String {
+    (1..).map(|n| format!("{}.bak{}", path, n)).find(|backup| !std::path::Path::new(backup).exists()).unwrap()
+}
+
+/* Save of another game is moved aside, not overwritten.
+ * Returns where it was moved to. */
+fn save_game(game: &engine::Game, path: &str, keep_previous: bool) -> Result<Option<String>, engine::SaveError> {
+    let backup = if keep_previous && std::path::Path::new(path).exists() {
+        let backup = backup_path(path);
+        std::fs::rename(path, &backup)?;
+        Some(backup)
+    } else {
+        None
+    };
+    let mut file = std::fs::File::create(path)?;
+    engine::save_game(game, &mut file)?;
+    Ok(backup)
+}
+
+fn load_game(path: &str) -> Result<engine::Game, engine::SaveError> {
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+    engine::load_game(&mut file)
+}
+
+/* Game resumed from save_path removes it once over, so it can't be resumed twice,
+ * new game keeps a backup of the save it replaces on quit.
+ * Autoplayer makes given number of inputs per second. */
+fn do_game(mut game: engine::Game, record: Option<&str>, save_path: &str, resumed: bool, autoplay: Option<u32>) {
+    let config = engine::config(&game).clone();
+    let (dimensions, seed) = (config.dimensions, config.seed);
+    let mut replay = Replay::new(config);
+    let mut save_result = None;
+
+    let (timer_tx, rx) = mpsc::channel();
+    let keyboard_tx = timer_tx.clone();
+
+    thread::spawn(move || {
+        /* timeout generator */
+        loop {
+            let _ = timer_tx.send(engine::Event::Timeout);
+            thread::sleep(Duration::from_micros(FRAME_INTERVAL));
+        }
+    });
+
+    let mut stdout = stdout().into_raw_mode().unwrap();
+    write!(stdout, "{}{}", termion::cursor::Hide, termion::clear::All).unwrap();
+    spawn_keyboard(keyboard_tx);
 
+    let mut autoplayer = autoplay.map(|speed| (AutoPlayer::new(Default::default()), FRAME_RATE / speed.clamp(1, FRAME_RATE)));
+    let mut frame: u32 = 0;
+    let mut view = view::ConsoleView::new(dimensions);
     while !engine::is_finished(&game) {
-        let mut view = view::ConsoleView{};
         let event = rx.recv().unwrap();
+        if event == engine::Event::KeyExit && !engine::is_finished(&game) {
+            save_result = Some(save_game(&game, save_path, !resumed));
+        }
+        replay.record(&event);
         engine::calculate_frame(&mut game, event.clone());
+        /* autoplayer presses keys the same way player does */
+        if let (engine::Event::Timeout, Some((player, interval))) = (&event, &mut autoplayer) {
+            frame += 1;
+            if frame.is_multiple_of(*interval) {
+                if let Some(input) = player.next_event(&game) {
+                    replay.record(&input);
+                    engine::calculate_frame(&mut game, input);
+                }
+            }
+        }
         engine::draw_frame(&mut game, &mut view);
     }
 
-    print!("Final score: {:?}\n\r", engine::final_score(&game));
+    show_result(&game, seed);
+    if let Some(path) = record {
+        if let Err(e) = replay.save(path) {
+            print!("Cannot save replay {}: {}\n\r", path, e.reason);
+        }
+    }
+    match save_result {
+        Some(Ok(backup)) => {
+            print!("Game saved to {}, continue with --resume\n\r", save_path);
+            if let Some(backup) = backup {
+                print!("Previously saved game was moved to {}, continue it with --resume {}\n\r", backup, backup);
+            }
+        },
+        Some(Err(e)) => print!("Cannot save game {}: {}\n\r", save_path, e.reason),
+        None if resumed => { let _ = std::fs::remove_file(save_path); },
+        None => {},
+    }
     write!(stdout, "{}", termion::cursor::Show).unwrap();
 }
 
-fn main() {
+/* Interactive replay viewer, keys are listed in the status line */
+fn do_replay(replay: Replay) {
+    let (dimensions, seed) = (replay.config.dimensions, replay.config.seed);
+    let mut player = match ReplayPlayer::new(&replay) {
+        Ok(player) => player,
+        Err(e) => {
+            println!("Cannot play replay: {}", e.reason);
+            std::process::exit(-1);
+        }
+    };
+
+    let (keyboard_tx, rx) = mpsc::channel();
+    let mut stdout = stdout().into_raw_mode().unwrap();
+    write!(stdout, "{}{}", termion::cursor::Hide, termion::clear::All).unwrap();
+    thread::spawn(move || {
+        for c in stdin().keys() {
+            let _ = keyboard_tx.send(c.unwrap());
+        }
+    });
+
+    let mut view = view::ConsoleView::new(dimensions);
+    /* number typed before piece or line jump */
+    let mut number = String::new();
+    'replay: loop {
+        let mut seeked = false;
+        while let Ok(key) = rx.try_recv() {
+            match key {
+                Key::Char('q') | Key::Ctrl('z') | Key::Ctrl('c') => break 'replay,
+                Key::Char(' ') => player.toggle_pause(),
+                Key::Right | Key::Left => {
+                    if !player.paused() {
+                        player.toggle_pause();
+                    }
+                    if key == Key::Right {
+                        player.step();
+                    } else {
+                        player.step_back();
+                        seeked = true;
+                    }
+                },
+                Key::Char('+') | Key::Char('=') => player.faster(),
+                Key::Char('-') => player.slower(),
+                Key::Char(c) if c.is_ascii_digit() => number.push(c),
+                Key::Char(c) if c == 'p' || c == 'l' => {
+                    if let Ok(n) = number.parse() {
+                        if c == 'p' { player.seek_piece(n); } else { player.seek_lines(n); }
+                        seeked = true;
+                    }
+                    number.clear();
+                },
+                _ => number.clear(),
+            }
+        }
+        if seeked {
+            /* old screen may show things that haven't happened yet */
+            write!(stdout, "{}", termion::clear::All).unwrap();
+            player.redraw();
+        }
+        if !player.paused() {
+            player.step();
+        }
+        player.draw(&mut view);
+        view.show_status(&format!("Frame {}/{} Piece {} Lines {} Speed {}x{} {}| Space: pause ⬅️ ➡️ : step +/-: speed Np/Nl: go to piece/lines q: quit",
+                                  player.frame(), player.frames(), engine::pieces(player.game()), engine::lines(player.game()),
+                                  player.speed(), if player.paused() { " paused" } else { "" }, number));
+        thread::sleep(Duration::from_micros((FRAME_INTERVAL as f32 / player.speed()) as u64));
+    }
+
+    show_result(player.game(), seed);
+    write!(stdout, "{}", termion::cursor::Show).unwrap();
+}
+
+/* Goal of --lines, a zero goal would be reached before the first piece */
+fn line_goal(matches: &ArgMatches, default: u32) -> u32 {
+    let lines = value_t!(matches, "lines", u32).unwrap_or(default);
+    if !(1..=engine::MAX_LINE_GOAL).contains(&lines) {
+        println!("Unsupported line goal {}. Use -h to list supported goals.", lines);
+        std::process::exit(-1);
+    }
+    lines
+}
+
+/* Returns false if terminal is too small to show playfield of given size */
+fn check_terminal(dimensions: playfield::Dimensions) -> bool {
+    let (min_width, min_height) = view::ConsoleView::new(dimensions).required_size();
+    let (min_width, min_height) = (std::cmp::max(min_width, 80), std::cmp::max(min_height, 25));
     match termion::terminal_size() {
         Ok(v) => {
             let (width, height) = v;
 
-            if width < 80 || height < 25 {
-                println!("Terminal size should be greater than (W:80, H:25). Given (W:{}, H:{})", width, height);
-                return;
+            if width < min_width || height < min_height {
+                println!("Terminal size should be greater than (W:{}, H:{}). Given (W:{}, H:{})",
+                         min_width, min_height, width, height);
+                return false;
             }
+            true
         }
         Err(e) => {
             println!("Cannot read terminal dimensions: {:?}", e);
-            return
+            false
         },
     }
+}
 
+fn main() {
     let matches = App::new("tetrust")
                     .version(env!("CARGO_PKG_VERSION"))
                     .author("Denis Vasilkovskii <digitalorder>")
@@ -84,9 +263,66 @@ fn main() {
                         "-g, --no-ghost 'Disables ghost tetro for easy dropping'
                          -l, --level [level] 'Start level (0-29)'
                          -n, --next-queue-size [size] 'Upcoming tetriminos queue size (0-4)'
-                         -m, --mode [marathon,sprint] 'Game mode'")
+                         -m, --mode [marathon,sprint,ultra,cheese,survival,master,zen] 'Game mode'
+                         -t, --time [seconds] 'Ultra mode time limit (1-3600, default 120)'
+                         --lines [count] 'Sprint mode line goal or cheese mode garbage rows to clear (1-1000, default 40 and 18)'
+                         -r, --rotation [srs,ars,nes] 'Rotation system'
+                         -p, --pieces [pentomino,FILE] 'Piece set to play with instead of tetrominoes'
+                         --width [columns] 'Playfield width (4-16)'
+                         --height [rows] 'Visible playfield height (4-40)'
+                         -c, --cascade 'Connected blocks fall as a whole after line clears'
+                         -s, --seed [seed] 'Seed for piece generator, same seed deals the same pieces'
+                         --record [FILE] 'Save replay of the game to a file'
+                         --resume [SAVE] 'Continue game saved on quit, other game options are ignored'
+                         -a, --autoplay 'Computer plays the game, keys still work'
+                         --autoplay-speed [inputs] 'Autoplayer inputs per second (1-60, default 10)'")
+                    .subcommand(SubCommand::with_name("replay")
+                                .about("Plays back recorded game")
+                                .arg_from_usage("<FILE> 'Replay file'"))
                     .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("replay") {
+        let path = matches.value_of("FILE").unwrap();
+        match Replay::load(path) {
+            Ok(replay) => {
+                if check_terminal(replay.config.dimensions) {
+                    do_replay(replay);
+                }
+            },
+            Err(e) => {
+                println!("Cannot load replay {}: {}", path, e.reason);
+                std::process::exit(-1);
+            }
+        }
+        return;
+    }
+
+    let autoplay = if matches.is_present("autoplay") {
+        Some(value_t!(matches, "autoplay-speed", u32).unwrap_or(10))
+    } else {
+        None
+    };
+
+    if matches.is_present("resume") {
+        let path = matches.value_of("resume").map_or_else(default_save_path, |p| p.to_string());
+        if matches.is_present("record") {
+            println!("Resumed game can't be recorded, replays start from an empty playfield");
+            std::process::exit(-1);
+        }
+        match load_game(&path) {
+            Ok(game) => {
+                if check_terminal(engine::config(&game).dimensions) {
+                    do_game(game, None, &path, true, autoplay);
+                }
+            },
+            Err(e) => {
+                println!("Cannot resume game {}: {}", path, e.reason);
+                std::process::exit(-1);
+            }
+        }
+        return;
+    }
+
     let no_ghost = matches.is_present("no-ghost");
     let level = value_t!(matches, "level", u8).unwrap_or(0);
     let next_queue_size = value_t!(matches, "next-queue-size", u8).unwrap_or(4);
@@ -94,11 +330,73 @@ fn main() {
     let mode = if mode_str == "marathon" {
         engine::Mode::Marathon
     } else if mode_str == "sprint" {
-        engine::Mode::Sprint
+        engine::Mode::Sprint{lines: line_goal(&matches, engine::SPRINT_LINES)}
+    } else if mode_str == "ultra" {
+        let seconds = value_t!(matches, "time", u32).unwrap_or(engine::ULTRA_SECONDS);
+        if !(1..=engine::MAX_ULTRA_SECONDS).contains(&seconds) {
+            println!("Unsupported time limit {} seconds. Use -h to list supported limits.", seconds);
+            std::process::exit(-1);
+        }
+        engine::Mode::Ultra{seconds: seconds}
+    } else if mode_str == "cheese" {
+        engine::Mode::Cheese{rows: line_goal(&matches, engine::CHEESE_ROWS)}
+    } else if mode_str == "survival" {
+        engine::Mode::Survival
+    } else if mode_str == "master" {
+        engine::Mode::Master
+    } else if mode_str == "zen" {
+        engine::Mode::Zen
     } else {
         println!("Unknown game mode {}. Use -h to list supported modes.", mode_str);
         std::process::exit(-1);
     };
-    println!("no ghost tetro: {} level: {} queue size: {} mode: {}", no_ghost, level, next_queue_size, mode);
-    do_game(no_ghost, level, next_queue_size, mode);
+    let rotation_str = value_t!(matches, "rotation", String).unwrap_or("srs".to_string());
+    let rotation_system = match rotation::by_name(&rotation_str) {
+        Some(system) => system,
+        None => {
+            println!("Unknown rotation system {}. Use -h to list supported systems.", rotation_str);
+            std::process::exit(-1);
+        }
+    };
+    /* piece set brings its own rotation states, so it replaces rotation system */
+    let rotation_system = match matches.value_of("pieces") {
+        None => rotation_system,
+        Some(path) => match piece_set::system_by_name(path) {
+            Ok(system) => system,
+            Err(e) => {
+                println!("Cannot load piece set {}:{}: {}", path, e.line, e.reason);
+                std::process::exit(-1);
+            }
+        },
+    };
+    let seed = value_t!(matches, "seed", u64).unwrap_or_else(|_| rand::random());
+    let clear_mode = if matches.is_present("cascade") {
+        playfield::ClearMode::Cascade
+    } else {
+        playfield::ClearMode::Naive
+    };
+    let dimensions = playfield::Dimensions::new(value_t!(matches, "width", i8).unwrap_or(playfield::WIDTH),
+                                                value_t!(matches, "height", i8).unwrap_or(playfield::HEIGHT));
+    if !dimensions.is_valid() {
+        println!("Unsupported playfield size {}x{}. Use -h to list supported sizes.", dimensions.width, dimensions.height);
+        std::process::exit(-1);
+    }
+
+    if !check_terminal(dimensions) {
+        return;
+    }
+
+    println!("no ghost tetro: {} level: {} queue size: {} mode: {} rotation: {} playfield: {}x{} seed: {}",
+             no_ghost, level, next_queue_size, mode, rotation_system.name(), dimensions.width, dimensions.height, seed);
+    let config = engine::Config{no_ghost: no_ghost, level: level, next_queue_size: next_queue_size, mode: mode,
+                                rotation_system: rotation_system, dimensions: dimensions, clear_mode: clear_mode,
+                                seed: seed};
+    let game = match engine::new_game(config) {
+        Ok(game) => game,
+        Err(e) => {
+            println!("Cannot start game: {}", e.reason);
+            std::process::exit(-1);
+        }
+    };
+    do_game(game, matches.value_of("record"), &default_save_path(), false, autoplay);
 }
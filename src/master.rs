@@ -0,0 +1,87 @@
+/* Master mode timings, levels go from 0 to 999 in sections of 100 */
+pub const MAX_LEVEL: u32 = 999;
+pub const SECTION: u32 = 100;
+
+/* Gravity is measured in 1/256 of a cell per frame */
+pub const CELL: u32 = 256;
+/* Piece falls through the whole playfield in a frame */
+pub const GRAVITY_20G: u32 = 20 * CELL;
+
+/* Gravity goes up at these levels */
+const GRAVITY: [(u32, u32); 30] = [
+    (0, 4), (30, 6), (35, 8), (40, 10), (50, 12), (60, 16), (70, 32), (80, 48), (90, 64), (100, 80),
+    (120, 96), (140, 112), (160, 128), (170, 144), (200, 4), (220, 32), (230, 64), (233, 96), (236, 128), (239, 160),
+    (243, 192), (247, 224), (251, 256), (300, 512), (330, 768), (360, 1024), (400, 1280), (420, 1024), (450, 768), (500, GRAVITY_20G),
+];
+
+/* Delays in frames */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Timings {
+    /* before next piece appears */
+    pub are: u32,
+    /* piece stays on the ground this long before it locks */
+    pub lock_delay: u32,
+    /* completed lines stay before they are removed */
+    pub line_clear: u32,
+}
+
+/* Timings get shorter from level 500 on, starting level of each section */
+const TIMINGS: [(u32, Timings); 6] = [
+    (0, Timings{are: 25, lock_delay: 30, line_clear: 40}),
+    (500, Timings{are: 25, lock_delay: 30, line_clear: 25}),
+    (600, Timings{are: 25, lock_delay: 30, line_clear: 16}),
+    (700, Timings{are: 16, lock_delay: 30, line_clear: 12}),
+    (800, Timings{are: 12, lock_delay: 30, line_clear: 6}),
+    (900, Timings{are: 12, lock_delay: 17, line_clear: 6}),
+];
+
+pub fn gravity(level: u32) -> u32 {
+    GRAVITY.iter().rev().find(|(start, _)| level >= *start).map_or(GRAVITY[0].1, |(_, gravity)| *gravity)
+}
+
+pub fn timings(level: u32) -> Timings {
+    TIMINGS.iter().rev().find(|(start, _)| level >= *start).map_or(TIMINGS[0].1, |(_, timings)| *timings)
+}
+
+/* A new piece moves level up unless it stands at the end of a section
+ * or right before the last level, only line clears go past these */
+pub fn piece_spawned(level: u32) -> u32 {
+    if level % SECTION == SECTION - 1 || level >= MAX_LEVEL - 1 {
+        level
+    } else {
+        level + 1
+    }
+}
+
+pub fn lines_cleared(level: u32, lines: u32) -> u32 {
+    std::cmp::min(level + lines, MAX_LEVEL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gravity_and_timings() {
+        assert_eq!(gravity(0), 4);
+        assert_eq!(gravity(34), 6);
+        assert_eq!(gravity(200), 4);
+        assert_eq!(gravity(251), CELL);
+        assert_eq!(gravity(499), 768);
+        assert_eq!(gravity(500), GRAVITY_20G);
+        assert_eq!(gravity(MAX_LEVEL), GRAVITY_20G);
+        assert_eq!(timings(499).line_clear, 40);
+        assert_eq!(timings(750), Timings{are: 16, lock_delay: 30, line_clear: 12});
+        assert_eq!(timings(MAX_LEVEL).lock_delay, 17);
+    }
+
+    #[test]
+    fn section_stops() {
+        assert_eq!(piece_spawned(0), 1);
+        assert_eq!(piece_spawned(99), 99);
+        assert_eq!(lines_cleared(99, 1), 100);
+        assert_eq!(piece_spawned(100), 101);
+        assert_eq!(piece_spawned(998), 998);
+        assert_eq!(lines_cleared(997, 4), MAX_LEVEL);
+    }
+}
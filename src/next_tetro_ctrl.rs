@@ -1,34 +1,64 @@
 use crate::updateable_view::{UpdatableView, Ctrl};
 use crate::view::{View, ShowArgs};
 use crate::figures::figures::{Tetrimino, Shape};
-use crate::playfield::{FieldTetrimino, Coords, HEIGHT, WIDTH};
-use rand::{thread_rng};
+use crate::playfield::{FieldTetrimino, Coords, Dimensions};
+use crate::rotation::{RotationSystem};
+use rand::{SeedableRng};
+use rand::rngs::{StdRng};
 use rand::seq::SliceRandom;
 
-const DRAW_SIZE: usize = 7;
-const BAG_SIZE: usize = DRAW_SIZE * 2;
+/* New piece as it appears on the playfield */
+pub fn spawn(shape: Shape, rotation_system: &'static dyn RotationSystem, dimensions: Dimensions) -> FieldTetrimino {
+    let tetro = Tetrimino::with_system(shape, rotation_system);
+    /* lowest row of the tetro appears on top of visible area */
+    FieldTetrimino{
+        coords: Coords{row: dimensions.height - 1 + tetro.bottom_row(), col: dimensions.width / 2 - 2},
+        tetro: tetro,
+    }
+}
 
+#[derive(Clone)]
 pub struct NextTetroCtrl {
     view: UpdatableView,
-    bag: [Shape; BAG_SIZE],
+    /* holds two draws, every draw is a shuffle of all shapes in the set */
+    bag: Vec<Shape>,
+    draw_size: usize,
     bag_index: usize,
     pushed_flag: bool,
     preview_size: usize,
+    rotation_system: &'static dyn RotationSystem,
+    dimensions: Dimensions,
+    /* seeded, so the same seed deals the same pieces */
+    rng: StdRng,
+    seed: u64,
+    /* draws taken from rng so far */
+    shuffles: u32,
 }
 
 pub struct AlreadyPushed;
 
+/* Everything needed to continue dealing the same pieces */
+#[derive(Clone, PartialEq, Debug)]
+pub struct BagState {
+    pub shapes: Vec<Shape>,
+    pub index: usize,
+    /* hold was used since last piece */
+    pub held: bool,
+    pub shuffles: u32,
+}
+
 impl NextTetroCtrl {
     /* Consume next item in upcoming queue and fill in empty spaces if they occur */
     fn draw_next(self: &mut Self, never_redraw: bool) -> Shape {
         let result = self.bag[self.bag_index].clone();
-        if self.bag_index < DRAW_SIZE - 1 || never_redraw {
+        if self.bag_index < self.draw_size - 1 || never_redraw {
             self.bag_index += 1;
         } else {
             /* used up current draw, time to generate a new one */
-            let (left, right) = self.bag.split_at_mut(DRAW_SIZE);
+            let (left, right) = self.bag.split_at_mut(self.draw_size);
             left.clone_from_slice(right);
-            right.clone_from_slice(&NextTetroCtrl::shuffle_bag());
+            right.clone_from_slice(&NextTetroCtrl::shuffle_bag(&self.rotation_system.shapes(), &mut self.rng));
+            self.shuffles += 1;
             self.bag_index = 0;
         };
         self.pushed_flag = false;
@@ -40,13 +70,7 @@ impl NextTetroCtrl {
     fn pop_impl(self: &mut Self, never_redraw: bool) -> FieldTetrimino {
         self.view.update();
         let shape = self.draw_next(never_redraw);
-        FieldTetrimino{
-            coords: match shape {
-                Shape::LShape | Shape::TShape | Shape::JShape => Coords{row: HEIGHT, col: WIDTH / 2 - 2},
-                _ => Coords{row: HEIGHT + 1, col: WIDTH / 2 - 2},
-            },
-            tetro: Tetrimino::new(shape),
-        }
+        spawn(shape, self.rotation_system, self.dimensions)
     }
 
     /* Consume next item from upcoming queue and make a proper Tetrimino out of it */
@@ -55,7 +79,7 @@ impl NextTetroCtrl {
     }
 
     /* Replace given shape with whatever is on top of upcoming queue */
-    pub fn swap(self: &mut Self, shape: Shape) -> Result<(FieldTetrimino), AlreadyPushed> {
+    pub fn swap(self: &mut Self, shape: Shape) -> Result<FieldTetrimino, AlreadyPushed> {
         if self.pushed_flag {
             return Err(AlreadyPushed{});
         }
@@ -70,50 +94,101 @@ impl NextTetroCtrl {
         if self.bag_index > 0 {
             self.bag_index -= 1;
         } else {
-            self.bag_index = DRAW_SIZE - 1;
+            self.bag_index = self.draw_size - 1;
         }
         self.bag[self.bag_index] = shape;
         self.pushed_flag = true;
     }
 
-    /* Produce new rearrangement of 7-tetro bag */
-    fn shuffle_bag() -> [Shape; DRAW_SIZE] {
-        let mut rnd = thread_rng();
-        let mut bag = [Shape::OShape, Shape::IShape,
-                       Shape::TShape, Shape::JShape,
-                       Shape::LShape, Shape::SShape,
-                       Shape::ZShape,];
-        bag.shuffle(&mut rnd);
+    /* Produce new rearrangement of given shapes, e.g. 7-tetro bag */
+    fn shuffle_bag(shapes: &[Shape], rng: &mut StdRng) -> Vec<Shape> {
+        let mut bag = shapes.to_vec();
+        bag.shuffle(rng);
         bag
     }
 
-    pub fn new(preview_size: usize) -> Self {
+    /* Upcoming pieces shown to the player */
+    pub fn preview(self: &Self) -> &[Shape] {
+        let preview_size = std::cmp::min(self.preview_size, self.draw_size);
+        &self.bag[self.bag_index..self.bag_index + preview_size]
+    }
+
+    /* Shape put back by hold, it waits on top of the queue until popped */
+    pub fn held(self: &Self) -> Option<Shape> {
+        if self.pushed_flag {
+            Some(self.bag[self.bag_index].clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn bag_state(self: &Self) -> BagState {
+        BagState{shapes: self.bag.clone(), index: self.bag_index, held: self.pushed_flag, shuffles: self.shuffles}
+    }
+
+    /* Returns false if state doesn't fit the piece set */
+    pub fn restore(self: &mut Self, state: BagState) -> bool {
+        let shapes = self.rotation_system.shapes();
+        if state.shapes.len() != self.bag.len() || state.index >= self.draw_size || state.shuffles < 2
+            || state.shapes.iter().any(|s| !shapes.contains(s)) {
+            return false;
+        }
+        /* rng can't be stored, so redo every draw taken from it */
+        self.rng = StdRng::seed_from_u64(self.seed);
+        for _ in 0..state.shuffles {
+            NextTetroCtrl::shuffle_bag(&shapes, &mut self.rng);
+        }
+        self.bag = state.shapes;
+        self.bag_index = state.index;
+        self.pushed_flag = state.held;
+        self.shuffles = state.shuffles;
+        self.view.update();
+        true
+    }
+
+    pub fn new(preview_size: usize, rotation_system: &'static dyn RotationSystem, dimensions: Dimensions, seed: u64) -> Self {
         /* do two shuffles and put them immediately inside bag */
-        let mut bag: [Shape; BAG_SIZE] = Default::default();
-        bag[0..DRAW_SIZE].clone_from_slice(&NextTetroCtrl::shuffle_bag());
-        bag[DRAW_SIZE..].clone_from_slice(&NextTetroCtrl::shuffle_bag());
+        let shapes = rotation_system.shapes();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut bag = NextTetroCtrl::shuffle_bag(&shapes, &mut rng);
+        bag.extend(NextTetroCtrl::shuffle_bag(&shapes, &mut rng));
         NextTetroCtrl{
             view: UpdatableView::new(true),
             bag: bag,
+            draw_size: shapes.len(),
             bag_index: 0,
             pushed_flag: false,
             preview_size: preview_size,
+            rotation_system: rotation_system,
+            dimensions: dimensions,
+            rng: rng,
+            seed: seed,
+            shuffles: 2,
         }
     }
 }
 
 impl Ctrl for NextTetroCtrl {
     fn show(self: &mut Self, view: &mut impl View) {
+        /* small sets can't fill the whole preview */
+        let preview_size = std::cmp::min(self.preview_size, self.draw_size);
         self.view.show(view, &ShowArgs::NextTetroArgs{
-            next: &self.bag[self.bag_index..self.bag_index + self.preview_size]
+            next: &self.bag[self.bag_index..self.bag_index + preview_size],
+            rotation_system: self.rotation_system,
         });
     }
+    fn redraw(self: &mut Self) {
+        self.view.update();
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::view::{ShowArgs, MAX_PREVIEW_SIZE};
+    use crate::rotation::{SRS};
+    use crate::piece_set::{PieceSet};
+    use crate::playfield::{Playfield, Storage};
 
     #[derive(Default)]
     struct TestView {
@@ -123,7 +198,7 @@ mod tests {
     impl View for TestView {
         fn show_subview(self: &mut Self, args: &ShowArgs) {
             match args {
-                ShowArgs::NextTetroArgs{next} => {
+                ShowArgs::NextTetroArgs{next, ..} => {
                     self.next.clone_from_slice(next);
                 }
                 _ => { panic!("unexpected!"); }
@@ -131,16 +206,77 @@ mod tests {
         }
     }
 
+    #[test]
+    fn pentomino_bag_deals_every_piece() {
+        let set: &'static PieceSet = Box::leak(Box::new(PieceSet::pentominoes()));
+        let mut ctrl = NextTetroCtrl::new(MAX_PREVIEW_SIZE, set, Default::default(), 0);
+        let playfield = Playfield::new(Default::default());
+        let mut names: Vec<char> = Vec::new();
+        for _ in 0..set.pieces().len() {
+            let tetro = ctrl.pop();
+            assert_eq!(playfield.can_place(&tetro.tetro, &tetro.coords), true);
+            match tetro.tetro.shape {
+                Shape::Custom{name, ..} => names.push(name),
+                shape => panic!("unexpected shape {:?}", shape),
+            }
+        }
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), set.pieces().len());
+    }
+
+    #[test]
+    fn spawn_inside_narrow_playfield() {
+        let dimensions = Dimensions::new(4, 4);
+        let mut ctrl = NextTetroCtrl::new(MAX_PREVIEW_SIZE, &SRS, dimensions, 0);
+        let playfield = Playfield::new(Storage::new(dimensions));
+        for _ in 0..ctrl.draw_size {
+            let tetro = ctrl.pop();
+            assert_eq!(playfield.can_place(&tetro.tetro, &tetro.coords), true, "{:?}", tetro.tetro.shape);
+            /* and it is still visible, touching top row */
+            assert_eq!(playfield.can_place(&tetro.tetro, &Coords{row: tetro.coords.row + 1, col: tetro.coords.col}), true);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_pieces() {
+        let shapes = |seed: u64| {
+            let mut ctrl = NextTetroCtrl::new(MAX_PREVIEW_SIZE, &SRS, Default::default(), seed);
+            (0..30).map(|_| ctrl.pop().tetro.shape).collect::<Vec<Shape>>()
+        };
+        assert_eq!(shapes(42), shapes(42));
+        assert_ne!(shapes(42), shapes(43));
+    }
+
+    #[test]
+    fn restored_bag_deals_same_pieces() {
+        let mut ctrl = NextTetroCtrl::new(MAX_PREVIEW_SIZE, &SRS, Default::default(), 5);
+        for _ in 0..10 {
+            ctrl.pop();
+        }
+        assert_eq!(ctrl.swap(Shape::TShape).is_ok(), true);
+        let mut restored = NextTetroCtrl::new(MAX_PREVIEW_SIZE, &SRS, Default::default(), 5);
+        assert_eq!(restored.restore(ctrl.bag_state()), true);
+        assert_eq!(restored.swap(Shape::TShape).is_err(), true);
+        for _ in 0..30 {
+            assert_eq!(restored.pop().tetro.shape, ctrl.pop().tetro.shape);
+        }
+
+        let mut state = ctrl.bag_state();
+        state.shapes.pop();
+        assert_eq!(restored.restore(state), false);
+    }
+
     #[test]
     fn cannot_swap_twice() {
-        let mut ctrl = NextTetroCtrl::new(MAX_PREVIEW_SIZE);
+        let mut ctrl = NextTetroCtrl::new(MAX_PREVIEW_SIZE, &SRS, Default::default(), 0);
         assert_eq!(ctrl.swap(Shape::IShape).is_ok(), true);
         assert_eq!(ctrl.swap(Shape::IShape).is_err(), true);
     }
 
     #[test]
     fn can_swap_after_pop() {
-        let mut ctrl = NextTetroCtrl::new(MAX_PREVIEW_SIZE);
+        let mut ctrl = NextTetroCtrl::new(MAX_PREVIEW_SIZE, &SRS, Default::default(), 0);
         assert_eq!(ctrl.swap(Shape::IShape).is_ok(), true);
         ctrl.pop();
         assert_eq!(ctrl.swap(Shape::IShape).is_ok(), true);
@@ -150,14 +286,14 @@ mod tests {
     fn pop_5_and_hold() {
         /* Corner case: pop 6 times and push back the last one.
          * It should not have effect on upcoming queue.*/
-        let mut ctrl = NextTetroCtrl::new(MAX_PREVIEW_SIZE);
+        let mut ctrl = NextTetroCtrl::new(MAX_PREVIEW_SIZE, &SRS, Default::default(), 0);
         let mut test_view: TestView = Default::default();
         ctrl.show(&mut test_view);
         let mut popped_shape = Shape::NoShape;
         let mut queue_after_pop = test_view.next.clone();
 
         /* pop queue 6 times and watch items propagating to the top */
-        for i in 0..DRAW_SIZE - 1 {
+        for i in 0..ctrl.draw_size - 1 {
             let queue_before_pop = test_view.next.clone();
             popped_shape = ctrl.pop().tetro.shape;
             ctrl.show(&mut test_view);
@@ -0,0 +1,99 @@
+use crate::updateable_view::{UpdatableView, Ctrl};
+use crate::view::{View, ShowArgs};
+use crate::fall::{FRAME_RATE};
+
+/* Game goes on after this many frames of countdown */
+const RESUME_COUNTDOWN: u32 = 3 * FRAME_RATE;
+
+#[derive(Clone)]
+pub struct PauseCtrl {
+    view: UpdatableView,
+    next_queue_size: i8,
+    paused: bool,
+    /* frames left before resume, zero while waiting for the player */
+    countdown: u32,
+}
+
+impl Ctrl for PauseCtrl {
+    fn show(self: &mut Self, view: &mut impl View) {
+        if self.paused {
+            let countdown = if self.countdown > 0 {
+                Some(self.countdown.div_ceil(FRAME_RATE))
+            } else {
+                None
+            };
+            self.view.show(view, &ShowArgs::PauseArgs{countdown: countdown, next_queue_size: self.next_queue_size});
+        }
+    }
+    fn redraw(self: &mut Self) {
+        self.view.update();
+    }
+}
+
+impl PauseCtrl {
+    pub fn pause(self: &mut Self) {
+        self.paused = true;
+        self.countdown = 0;
+        self.view.update();
+    }
+
+    pub fn resume(self: &mut Self) {
+        self.countdown = RESUME_COUNTDOWN;
+        self.view.update();
+    }
+
+    pub fn counting_down(self: &Self) -> bool {
+        self.countdown > 0
+    }
+
+    /* Returns true when countdown is over */
+    pub fn tick(self: &mut Self) -> bool {
+        if self.countdown == 0 {
+            return false;
+        }
+        self.countdown -= 1;
+        if self.countdown.is_multiple_of(FRAME_RATE) {
+            self.view.update();
+        }
+        self.countdown == 0
+    }
+
+    pub fn stop(self: &mut Self) {
+        self.paused = false;
+        self.countdown = 0;
+    }
+
+    pub fn new(next_queue_size: usize) -> Self {
+        PauseCtrl{
+            view: UpdatableView::new(false),
+            next_queue_size: next_queue_size as i8,
+            paused: false,
+            countdown: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn countdown_after_resume() {
+        let mut pause = PauseCtrl::new(1);
+        pause.pause();
+        /* nothing happens until player resumes */
+        assert_eq!(pause.tick(), false);
+        assert_eq!(pause.counting_down(), false);
+        pause.resume();
+        assert_eq!(pause.counting_down(), true);
+        for _ in 1..RESUME_COUNTDOWN {
+            assert_eq!(pause.tick(), false);
+        }
+        assert_eq!(pause.tick(), true);
+        /* pausing during countdown waits for the player again */
+        pause.resume();
+        pause.tick();
+        pause.pause();
+        assert_eq!(pause.counting_down(), false);
+    }
+}
@@ -0,0 +1,388 @@
+use crate::figures::figures::{Shape, Layout, Color, Orientation, Rotation, LAYOUT_WIDTH, LAYOUT_HEIGHT};
+use crate::rotation::{self, RotationSystem};
+use crate::playfield::Coords;
+
+/* Piece set description format:
+ *
+ *   ; comment
+ *   piece F 5 2 0
+ *   .##
+ *   ##.
+ *   .#.
+ *
+ * Header line holds piece name and color components (0-5). It is followed
+ * by one or more states separated by blank lines. Every state is a grid of
+ * up to 5x5 cells where '.' is empty and anything else is filled. A single
+ * state gets turned around the center of its square box to produce the
+ * other three, two states toggle, four states are used as given in
+ * spawn, right, reverse, left order. */
+
+pub struct PieceDef {
+    pub name: char,
+    pub color: Color,
+    states: [Layout; 4],
+}
+
+pub struct PieceSet {
+    name: String,
+    pieces: Vec<PieceDef>,
+    /* of the text the set was parsed from */
+    digest: u64,
+    /* sets not built into the game keep their text for replays and saves */
+    text: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub reason: String,
+}
+
+/* Custom pieces don't have kick tables, so try a few nearby cells instead */
+static CUSTOM_KICKS: [Coords; 6] = [
+    Coords{col: 0, row: 0}, Coords{col: 1, row: 0}, Coords{col: -1, row: 0},
+    Coords{col: 0, row: 1}, Coords{col: 2, row: 0}, Coords{col: -2, row: 0},
+];
+
+/* One-sided pentominoes, mirrored pieces use lower case names */
+const PENTOMINOES: &str = "
+piece F 5 2 0
+.##
+##.
+.#.
+piece f 4 1 0
+##.
+.##
+.#.
+piece I 1 5 5
+.....
+.....
+#####
+piece L 5 3 0
+...#
+####
+piece l 0 0 5
+#...
+####
+piece N 3 5 0
+##..
+.###
+piece n 2 3 0
+..##
+###.
+piece P 5 4 3
+##.
+##.
+#..
+piece p 4 3 2
+.##
+.##
+..#
+piece T 5 0 5
+###
+.#.
+.#.
+piece U 3 0 5
+#.#
+###
+piece V 0 3 5
+#..
+#..
+###
+piece W 5 5 0
+#..
+##.
+.##
+piece X 5 5 5
+.#.
+###
+.#.
+piece Y 0 5 2
+..#.
+####
+piece y 0 4 1
+.#..
+####
+piece Z 5 0 0
+##.
+.#.
+.##
+piece z 0 5 0
+.##
+.#.
+##.
+";
+
+/* Turn square box of given size clockwise */
+fn rotate_box(layout: &Layout, size: usize) -> Layout {
+    let mut turned: Layout = Default::default();
+    for row in 0..size {
+        for col in 0..size {
+            turned[row][col] = layout[size - 1 - col][row];
+        }
+    }
+    turned
+}
+
+fn parse_state(lines: &[(usize, &str)]) -> Result<(Layout, usize), ParseError> {
+    let mut layout: Layout = Default::default();
+    let mut size = lines.len();
+    for (row, (line_no, line)) in lines.iter().enumerate() {
+        let line = line.trim_end();
+        if row >= LAYOUT_HEIGHT as usize || line.chars().count() > LAYOUT_WIDTH as usize {
+            return Err(ParseError{line: *line_no, reason: format!("piece doesn't fit {}x{} box", LAYOUT_WIDTH, LAYOUT_HEIGHT)});
+        }
+        size = std::cmp::max(size, line.chars().count());
+        for (col, c) in line.chars().enumerate() {
+            if c != '.' {
+                layout[row][col] = 1;
+            }
+        }
+    }
+    Ok((layout, size))
+}
+
+struct PieceBlock<'a> {
+    line: usize,
+    name: char,
+    color: Color,
+    states: Vec<Vec<(usize, &'a str)>>,
+}
+
+fn parse_header(line_no: usize, fields: &str) -> Result<(char, Color), ParseError> {
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    if fields.len() != 4 || fields[0].chars().count() != 1 || fields[0] == "." {
+        return Err(ParseError{line: line_no, reason: "expected: piece <letter> <r> <g> <b>".to_string()});
+    }
+    let mut color = [0u8; 3];
+    for (component, field) in color.iter_mut().zip(fields[1..].iter()) {
+        *component = match field.parse::<u8>() {
+            Ok(v) if v <= 5 => v,
+            _ => return Err(ParseError{line: line_no, reason: format!("bad color component {}", field)}),
+        };
+    }
+    Ok((fields[0].chars().next().unwrap(), (color[0], color[1], color[2])))
+}
+
+fn build_piece(block: &PieceBlock) -> Result<PieceDef, ParseError> {
+    let mut parsed = Vec::new();
+    for state in block.states.iter().filter(|s| !s.is_empty()) {
+        let (layout, size) = parse_state(state)?;
+        if layout.iter().all(|row| row.iter().all(|c| *c == 0)) {
+            return Err(ParseError{line: state[0].0, reason: "state without cells".to_string()});
+        }
+        parsed.push((layout, size));
+    }
+    let states = match parsed.len() {
+        1 => {
+            let (spawn, size) = parsed[0];
+            let right = rotate_box(&spawn, size);
+            let reverse = rotate_box(&right, size);
+            [spawn, right, reverse, rotate_box(&reverse, size)]
+        },
+        2 => [parsed[0].0, parsed[1].0, parsed[0].0, parsed[1].0],
+        4 => [parsed[0].0, parsed[1].0, parsed[2].0, parsed[3].0],
+        n => return Err(ParseError{line: block.line, reason: format!("expected 1, 2 or 4 states, got {}", n)}),
+    };
+    Ok(PieceDef{name: block.name, color: block.color, states: states})
+}
+
+/* 64-bit FNV-1a, stable across builds unlike std hashers */
+fn digest(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+impl PieceSet {
+    pub fn parse(name: &str, text: &str) -> Result<PieceSet, ParseError> {
+        /* split text into per piece blocks first */
+        let mut blocks: Vec<PieceBlock> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_no = index + 1;
+            let line = line.trim();
+            if line.starts_with(';') {
+                continue;
+            }
+            if let Some(fields) = line.strip_prefix("piece ") {
+                let (name, color) = parse_header(line_no, fields)?;
+                if blocks.iter().any(|b| b.name == name) {
+                    return Err(ParseError{line: line_no, reason: format!("duplicate piece {}", name)});
+                }
+                blocks.push(PieceBlock{line: line_no, name: name, color: color, states: vec![Vec::new()]});
+                continue;
+            }
+            match blocks.last_mut() {
+                Some(block) if line.is_empty() => block.states.push(Vec::new()),
+                Some(block) => block.states.last_mut().unwrap().push((line_no, line)),
+                None if line.is_empty() => {},
+                None => return Err(ParseError{line: line_no, reason: "cells given before piece header".to_string()}),
+            }
+        }
+
+        if blocks.is_empty() {
+            return Err(ParseError{line: 0, reason: "no pieces defined".to_string()});
+        }
+        if blocks.len() > u8::MAX as usize {
+            return Err(ParseError{line: 0, reason: "too many pieces".to_string()});
+        }
+        let mut pieces = Vec::new();
+        for block in blocks.iter() {
+            pieces.push(build_piece(block)?);
+        }
+        Ok(PieceSet{name: name.to_string(), pieces: pieces, digest: digest(text), text: None})
+    }
+
+    /* Set given as text, e.g. the one stored in a replay. Line endings are
+     * normalized so the text and its digest survive a round trip. */
+    pub fn from_text(name: &str, text: &str) -> Result<PieceSet, ParseError> {
+        let text: String = text.lines().map(|line| format!("{}\n", line)).collect();
+        let mut set = PieceSet::parse(name, &text)?;
+        set.text = Some(text);
+        Ok(set)
+    }
+
+    /* Named after the file, so the name doesn't depend on where the file was */
+    pub fn load(path: &str) -> Result<PieceSet, ParseError> {
+        let name = std::path::Path::new(path).file_stem().map_or(path.to_string(), |stem| stem.to_string_lossy().to_string());
+        match std::fs::read_to_string(path) {
+            Ok(text) => PieceSet::from_text(&name, &text),
+            Err(e) => Err(ParseError{line: 0, reason: e.to_string()}),
+        }
+    }
+
+    pub fn pentominoes() -> PieceSet {
+        PieceSet::parse("pentomino", PENTOMINOES).unwrap()
+    }
+
+    pub fn pieces(self: &Self) -> &[PieceDef] {
+        &self.pieces
+    }
+
+    fn piece(self: &Self, shape: &Shape) -> Option<&PieceDef> {
+        match shape {
+            Shape::Custom{index, ..} => self.pieces.get(*index as usize),
+            _ => None,
+        }
+    }
+}
+
+/* Built-in rotation system, "pentomino" or path to piece set file.
+ * Loaded sets are leaked, they are needed until the program ends. */
+pub fn system_by_name(name: &str) -> Result<&'static dyn RotationSystem, ParseError> {
+    if let Some(system) = rotation::by_name(name) {
+        return Ok(system);
+    }
+    let set = if name == "pentomino" { PieceSet::pentominoes() } else { PieceSet::load(name)? };
+    Ok(Box::leak(Box::new(set)))
+}
+
+/* Piece set stored in a replay or saved game, leaked like the loaded ones */
+pub fn system_from_text(name: &str, text: &str) -> Result<&'static dyn RotationSystem, ParseError> {
+    Ok(Box::leak(Box::new(PieceSet::from_text(name, text)?)))
+}
+
+impl RotationSystem for PieceSet {
+    fn name(self: &Self) -> &str {
+        &self.name
+    }
+
+    fn layout(self: &Self, shape: &Shape, orientation: Orientation) -> Layout {
+        match self.piece(shape) {
+            Some(piece) => piece.states[orientation as usize],
+            None => Default::default(),
+        }
+    }
+
+    fn kicks(self: &Self, _shape: &Shape, _from: Orientation, _rotation: Rotation) -> &'static [Coords] {
+        &CUSTOM_KICKS
+    }
+
+    fn spawn_orientation(self: &Self, _shape: &Shape) -> Orientation {
+        Orientation::Spawn
+    }
+
+    fn shapes(self: &Self) -> Vec<Shape> {
+        self.pieces.iter().enumerate()
+            .map(|(index, p)| Shape::Custom{index: index as u8, name: p.name, color: p.color})
+            .collect()
+    }
+
+    fn digest(self: &Self) -> Option<u64> {
+        Some(self.digest)
+    }
+
+    fn text(self: &Self) -> Option<&str> {
+        self.text.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(layout: &Layout) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        for (row, line) in layout.iter().enumerate() {
+            for (col, c) in line.iter().enumerate() {
+                if *c != 0 {
+                    cells.push((row, col));
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn pentominoes_have_five_cells() {
+        let set = PieceSet::pentominoes();
+        assert_eq!(set.pieces().len(), 18);
+        for shape in set.shapes() {
+            for orientation in [Orientation::Spawn, Orientation::Right, Orientation::Reverse, Orientation::Left].iter() {
+                assert_eq!(cells(&set.layout(&shape, *orientation)).len(), 5, "{:?} {:?}", shape, orientation);
+            }
+        }
+    }
+
+    #[test]
+    fn single_state_is_turned_clockwise() {
+        let set = PieceSet::parse("test", "piece L 5 3 0\n#.\n#.\n##\n").unwrap();
+        let shape = set.shapes()[0].clone();
+        assert_eq!(shape, Shape::Custom{index: 0, name: 'L', color: (5, 3, 0)});
+        assert_eq!(cells(&set.layout(&shape, Orientation::Spawn)), vec![(0, 0), (1, 0), (2, 0), (2, 1)]);
+        assert_eq!(cells(&set.layout(&shape, Orientation::Right)), vec![(0, 0), (0, 1), (0, 2), (1, 0)]);
+    }
+
+    #[test]
+    fn explicit_states() {
+        let text = "; domino\npiece D 1 1 1\n##\n\n#\n#\n";
+        let set = PieceSet::parse("test", text).unwrap();
+        let shape = set.shapes()[0].clone();
+        assert_eq!(cells(&set.layout(&shape, Orientation::Spawn)), vec![(0, 0), (0, 1)]);
+        assert_eq!(cells(&set.layout(&shape, Orientation::Right)), vec![(0, 0), (1, 0)]);
+        assert_eq!(cells(&set.layout(&shape, Orientation::Reverse)), vec![(0, 0), (0, 1)]);
+    }
+
+    fn error_line(text: &str) -> usize {
+        match PieceSet::parse("test", text) {
+            Err(e) => e.line,
+            Ok(_) => panic!("parsed invalid set: {:?}", text),
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(PieceSet::parse("test", "").is_err(), true);
+        assert_eq!(error_line("##\n"), 1);
+        assert_eq!(error_line("piece A 6 0 0\n#\n"), 1);
+        assert_eq!(error_line("piece A 1 0 0\n######\n"), 2);
+        assert_eq!(error_line("piece A 1 0 0\n#\npiece A 1 0 0\n#\n"), 3);
+        assert_eq!(error_line("piece A 1 0 0\n#\n\n#\n\n#\n"), 1);
+    }
+
+    #[test]
+    fn digest_follows_text() {
+        let set = PieceSet::parse("test", "piece D 1 1 1\n##\n").unwrap();
+        assert_eq!(set.digest(), PieceSet::parse("other", "piece D 1 1 1\n##\n").unwrap().digest());
+        assert_eq!(set.digest() == PieceSet::parse("test", "piece D 1 1 1\n###\n").unwrap().digest(), false);
+        assert_eq!(crate::rotation::SRS.digest(), None);
+    }
+}
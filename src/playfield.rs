@@ -1,20 +1,103 @@
 use crate::figures::*;
 
+/* Default (guideline) playfield size */
 pub const WIDTH: i8 = 10;
 pub const HEIGHT: i8 = 20;
-const TOTAL_HEIGHT: i8 = 30;
+pub const BUFFER_HEIGHT: i8 = 10;
+
+/* Bit per playfield cell, bit number is column */
+pub type RowMask = u16;
+pub const MAX_WIDTH: i8 = RowMask::BITS as i8;
+pub const MIN_WIDTH: i8 = figures::LAYOUT_WIDTH - 1;
+pub const MIN_HEIGHT: i8 = figures::LAYOUT_HEIGHT - 1;
+pub const MAX_HEIGHT: i8 = 40;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Dimensions {
+    pub width: i8,
+    /* visible rows */
+    pub height: i8,
+    /* hidden rows above visible area where pieces spawn and may be locked */
+    pub buffer: i8,
+}
+
+impl Default for Dimensions {
+    fn default() -> Self {
+        Dimensions{width: WIDTH, height: HEIGHT, buffer: BUFFER_HEIGHT}
+    }
+}
+
+impl Dimensions {
+    pub fn new(width: i8, height: i8) -> Dimensions {
+        Dimensions{width: width, height: height, buffer: BUFFER_HEIGHT}
+    }
+
+    pub fn total_height(self: &Self) -> i8 {
+        self.height + self.buffer
+    }
 
-type PlayfieldStorage = [[figures::Shape; WIDTH as usize]; TOTAL_HEIGHT as usize];
+    pub fn is_valid(self: &Self) -> bool {
+        (MIN_WIDTH..=MAX_WIDTH).contains(&self.width) &&
+            (MIN_HEIGHT..=MAX_HEIGHT).contains(&self.height) &&
+            self.buffer >= figures::LAYOUT_HEIGHT
+    }
 
+    fn full_row(self: &Self) -> RowMask {
+        ((1u32 << self.width) - 1) as RowMask
+    }
+}
+
+/* What happens to blocks above cleared lines */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ClearMode {
+    /* everything above moves down by one row per cleared line */
+    Naive,
+    /* connected groups of blocks fall until they land */
+    Cascade,
+}
+
+#[derive(Clone)]
 pub struct Storage {
-    playfield: PlayfieldStorage,
+    dimensions: Dimensions,
+    /* occupancy is kept as bitmasks, so collisions take a couple of operations per row */
+    rows: Vec<RowMask>,
+    /* shape of every filled cell, needed for colors only */
+    shapes: Vec<Vec<figures::Shape>>,
+}
+
+impl Storage {
+    pub fn new(dimensions: Dimensions) -> Storage {
+        let total_height = dimensions.total_height() as usize;
+        Storage{
+            dimensions: dimensions,
+            rows: vec![0; total_height],
+            shapes: vec![vec![figures::Shape::NoShape; dimensions.width as usize]; total_height],
+        }
+    }
+
+    pub fn dimensions(self: &Self) -> Dimensions {
+        self.dimensions
+    }
+
+    /* Shape of stored cell, coords must be inside of storage */
+    pub fn cell(self: &Self, coords: &Coords) -> &figures::Shape {
+        &self.shapes[coords.row as usize][coords.col as usize]
+    }
+
+    pub fn set_cell(self: &mut Self, coords: &Coords, shape: figures::Shape) {
+        let bit = 1 << coords.col;
+        if shape == figures::Shape::NoShape {
+            self.rows[coords.row as usize] &= !bit;
+        } else {
+            self.rows[coords.row as usize] |= bit;
+        }
+        self.shapes[coords.row as usize][coords.col as usize] = shape;
+    }
 }
 
 impl Default for Storage {
     fn default() -> Self {
-        Storage {
-            playfield: Default::default(),
-        }
+        Storage::new(Dimensions::default())
     }
 }
 
@@ -53,12 +136,15 @@ pub struct ShapeAt {
     pub shape: figures::Shape,
 }
 
+#[derive(Clone)]
 pub struct Playfield {
     storage: Storage,
 }
 
 pub struct OutOfBoundsError;
 
+pub struct TopOutError;
+
 #[derive(Copy, Clone)]
 pub struct Coords {
     pub row: i8,
@@ -66,20 +152,36 @@ pub struct Coords {
 }
 
 impl Playfield {
-    pub fn place(self: &mut Self, figure: &figures::Tetrimino, coords: Coords) -> Result<(), OutOfBoundsError> {
-        if self.can_place(figure, &coords) {
-            for row in 0..figures::LAYOUT_WIDTH {
-                for col in 0..figures::LAYOUT_HEIGHT {
-                    /* index may go out of bounds for empty parts of figure,
-                     * so we need to check for that */
-                    if coords.row < row || coords.col + col < 0 {
-                        continue;
-                    }
+    /* Move piece row mask to given playfield column.
+     * Returns None if any cell ends up outside of playfield. */
+    fn shift_mask(self: &Self, mask: u8, col: i8) -> Option<RowMask> {
+        let mask = mask as i32;
+        let shifted = if col >= 0 {
+            mask << col
+        } else if mask & ((1 << -col) - 1) != 0 {
+            return None;
+        } else {
+            mask >> -col
+        };
 
-                    let shape = figure.shape_at(&Coords{row: row, col: col});
+        if shifted >> self.storage.dimensions.width != 0 {
+            None
+        } else {
+            Some(shifted as RowMask)
+        }
+    }
 
-                    if shape != figures::Shape::NoShape {
-                        self.storage.playfield[(coords.row - row) as usize][(coords.col + col) as usize] = shape;
+    pub fn place(self: &mut Self, figure: &figures::Tetrimino, coords: Coords) -> Result<(), OutOfBoundsError> {
+        if self.can_place(figure, &coords) {
+            for (row, mask) in figure.row_masks().iter().enumerate() {
+                if *mask == 0 {
+                    continue;
+                }
+                let field_row = (coords.row - row as i8) as usize;
+                self.storage.rows[field_row] |= self.shift_mask(*mask, coords.col).unwrap();
+                for col in 0..figures::LAYOUT_WIDTH {
+                    if mask & (1 << col) != 0 {
+                        self.storage.shapes[field_row][(coords.col + col) as usize] = figure.shape.clone();
                     }
                 }
             }
@@ -91,33 +193,31 @@ impl Playfield {
     }
 
     pub fn can_place(self: &Self, figure: &figures::Tetrimino, coords: &Coords) -> bool {
-        for row in 0..figures::LAYOUT_WIDTH {
-            for col in 0..figures::LAYOUT_HEIGHT {
-                if figure.shape_at(&Coords{row: row, col: col}) != figures::Shape::NoShape {
-                    if row > coords.row || coords.row >= row + TOTAL_HEIGHT {
-                        return false;
-                    }
-                    if coords.col + col >= WIDTH || coords.col + col < 0 {
-                        return false;
-                    }
-                    if self.storage.playfield[(coords.row - row) as usize][(coords.col + col) as usize] != figures::Shape::NoShape {
-                        return false;
-                    }
-                }
+        for (row, mask) in figure.row_masks().iter().enumerate() {
+            if *mask == 0 {
+                continue;
             }
-        };
+            let field_row = coords.row - row as i8;
+            if !(0..self.storage.dimensions.total_height()).contains(&field_row) {
+                return false;
+            }
+            match self.shift_mask(*mask, coords.col) {
+                Some(shifted) if shifted & self.storage.rows[field_row as usize] == 0 => {},
+                _ => return false,
+            }
+        }
 
         true
     }
 
     pub fn row_filled(self: &Self, row: i8) -> bool {
-        for col in 0..WIDTH {
-            if self.storage.playfield[row as usize][col as usize] == figures::Shape::NoShape {
-                return false;
-            }
-        }
+        self.storage.rows[row as usize] == self.storage.dimensions.full_row()
+    }
 
-        true
+    /* Row has cells left from inserted garbage */
+    pub fn garbage_row(self: &Self, row: i8) -> bool {
+        (0..self.storage.dimensions.total_height()).contains(&row)
+            && self.storage.shapes[row as usize].contains(&figures::Shape::Garbage)
     }
 
     /**
@@ -130,19 +230,20 @@ impl Playfield {
      * Second item is true if given shape belongs to active tetromino.
      */
     pub fn shape_at(self: &Self, coords: &Coords, active_tetro: &FieldTetrimino, ghost_tetro: &FieldTetrimino) -> ShapeAt {
-        if coords.col < 0 || coords.col > WIDTH || coords.row < 0 || coords.row > HEIGHT {
+        let dimensions = &self.storage.dimensions;
+        if coords.col < 0 || coords.col >= dimensions.width || coords.row < 0 || coords.row >= dimensions.total_height() {
             ShapeAt{shape: figures::Shape::NoShape, shape_at_type: ShapeAtType::Static}
         } else {
             let (inside_active, active_coords) = if active_tetro.tetro.shape == figures::Shape::NoShape {
                 (false, Coords{row: 0, col: 0})
             } else {
-                Playfield::inside_tetro_coords(&coords, &active_tetro.coords)
+                Playfield::inside_tetro_coords(coords, &active_tetro.coords)
             };
 
             let (inside_ghost, ghost_coords) = if ghost_tetro.tetro.shape == figures::Shape::NoShape {
                 (false, Coords{row: 0, col: 0})
             } else {
-                Playfield::inside_tetro_coords(&coords, &ghost_tetro.coords)
+                Playfield::inside_tetro_coords(coords, &ghost_tetro.coords)
             };
 
             if inside_active && active_tetro.tetro.shape_at(&active_coords) != figures::Shape::NoShape {
@@ -153,7 +254,7 @@ impl Playfield {
                 return ShapeAt{shape: active_tetro.tetro.shape.clone(), shape_at_type: ShapeAtType::Ghost};
             }
 
-            return ShapeAt{shape: self.storage.playfield[coords.row as usize][coords.col as usize].clone(), shape_at_type: ShapeAtType::Static}
+            ShapeAt{shape: self.storage.shapes[coords.row as usize][coords.col as usize].clone(), shape_at_type: ShapeAtType::Static}
         }
     }
 
@@ -182,30 +283,175 @@ impl Playfield {
         self.can_place(&tetro.tetro, &move_down_coords)
     }
 
-    pub fn turn_tetro(self: &Self, tetro: &mut FieldTetrimino) -> bool {
+    pub fn turn_tetro(self: &Self, tetro: &mut FieldTetrimino, rotation: figures::Rotation) -> bool {
         if tetro.tetro.shape == figures::Shape::NoShape {
             return false;
         }
 
-        let mut turned_tetro = tetro.clone();
-        figures::rotate(&mut turned_tetro.tetro);
+        let mut turned_tetro = tetro.tetro.clone();
+        figures::turn(&mut turned_tetro, rotation);
 
-        if self.can_place(&turned_tetro.tetro, &turned_tetro.coords) {
-            *tetro = turned_tetro;
-            return true;
+        /* try every kick offset in order, first one that fits wins */
+        for kick in tetro.tetro.kicks(rotation) {
+            let coords = Coords{row: tetro.coords.row + kick.row, col: tetro.coords.col + kick.col};
+            if self.can_place(&turned_tetro, &coords) {
+                tetro.tetro = turned_tetro;
+                tetro.coords = coords;
+                return true;
+            }
         }
 
         false
     }
 
     pub fn delete_row(self: &mut Self, row: i8) {
-        if row < 0 || row > TOTAL_HEIGHT {
+        let total_height = self.storage.dimensions.total_height();
+        if !(0..total_height).contains(&row) {
             return;
         }
 
-        for r in row..TOTAL_HEIGHT-1 {
-            for c in 0..(WIDTH as usize) {
-                self.storage.playfield[r as usize][c] = self.storage.playfield[(r + 1) as usize][c].clone();
+        let row = row as usize;
+        let top = total_height as usize - 1;
+        self.storage.rows.copy_within(row + 1.., row);
+        self.storage.rows[top] = 0;
+        self.storage.shapes[row..].rotate_left(1);
+        self.storage.shapes[top] = vec![figures::Shape::NoShape; self.storage.dimensions.width as usize];
+    }
+
+    /* Push the stack up by count rows and fill the bottom with garbage,
+     * leaving hole columns empty. Active tetro is pushed up as well
+     * when it overlaps. Playfield and active tetro are left unchanged on top-out. */
+    pub fn insert_garbage(self: &mut Self, count: i8, holes: &[i8], active_tetro: &mut FieldTetrimino) -> Result<(), TopOutError> {
+        let dimensions = self.storage.dimensions;
+        let total_height = dimensions.total_height();
+        if count <= 0 {
+            return Ok(());
+        }
+        if count > total_height || self.storage.rows[(total_height - count) as usize..].iter().any(|r| *r != 0) {
+            return Err(TopOutError{});
+        }
+
+        let mut mask = dimensions.full_row();
+        for hole in holes.iter().filter(|h| (0..dimensions.width).contains(h)) {
+            mask &= !(1 << hole);
+        }
+        /* raise a copy, so nothing changes until active tetro is known to fit */
+        let mut raised = self.clone();
+        let count = count as usize;
+        /* empty top rows wrap around to the bottom and get filled */
+        raised.storage.rows.rotate_right(count);
+        raised.storage.shapes.rotate_right(count);
+        for row in 0..count {
+            raised.storage.rows[row] = mask;
+            for (col, shape) in raised.storage.shapes[row].iter_mut().enumerate() {
+                *shape = if mask & (1 << col) != 0 { figures::Shape::Garbage } else { figures::Shape::NoShape };
+            }
+        }
+
+        let mut coords = active_tetro.coords;
+        if active_tetro.tetro.shape != figures::Shape::NoShape {
+            let lift = (0..=count as i8).find(|lift| raised.can_place(&active_tetro.tetro, &Coords{row: coords.row + lift, col: coords.col}));
+            match lift {
+                Some(lift) => coords.row += lift,
+                None => return Err(TopOutError{}),
+            }
+        }
+        *self = raised;
+        active_tetro.coords = coords;
+        Ok(())
+    }
+
+    /* Empty the row without moving anything above it */
+    pub fn clear_row(self: &mut Self, row: i8) {
+        if !(0..self.storage.dimensions.total_height()).contains(&row) {
+            return;
+        }
+
+        for col in 0..self.storage.dimensions.width {
+            self.storage.set_cell(&Coords{row: row, col: col}, figures::Shape::NoShape);
+        }
+    }
+
+    /* Groups of filled cells touching each other by side, lowest groups first */
+    fn connected_groups(self: &Self) -> Vec<Vec<Coords>> {
+        let dimensions = self.storage.dimensions;
+        let mut visited = vec![0 as RowMask; dimensions.total_height() as usize];
+        let mut groups = Vec::new();
+        for row in 0..dimensions.total_height() {
+            for col in 0..dimensions.width {
+                if (self.storage.rows[row as usize] & !visited[row as usize]) & (1 << col) == 0 {
+                    continue;
+                }
+                /* rows are scanned bottom up, so first cell is the lowest one */
+                let mut group = Vec::new();
+                let mut pending = vec![Coords{row: row, col: col}];
+                visited[row as usize] |= 1 << col;
+                while let Some(cell) = pending.pop() {
+                    for (dr, dc) in [(1, 0), (-1, 0), (0, 1), (0, -1)].iter() {
+                        let (r, c) = (cell.row + dr, cell.col + dc);
+                        if (0..dimensions.total_height()).contains(&r) && (0..dimensions.width).contains(&c) &&
+                                (self.storage.rows[r as usize] & !visited[r as usize]) & (1 << c) != 0 {
+                            visited[r as usize] |= 1 << c;
+                            pending.push(Coords{row: r, col: c});
+                        }
+                    }
+                    group.push(cell);
+                }
+                groups.push(group);
+            }
+        }
+        groups
+    }
+
+    /* Let every connected group of cells fall until it lands.
+     * Returns true if anything has moved. */
+    pub fn cascade(self: &mut Self) -> bool {
+        let mut moved = false;
+        loop {
+            let mut step_moved = false;
+            for group in self.connected_groups() {
+                /* lift group off the playfield, so it doesn't collide with itself */
+                let shapes: Vec<figures::Shape> = group.iter().map(|c| self.storage.cell(c).clone()).collect();
+                for cell in group.iter() {
+                    self.storage.set_cell(cell, figures::Shape::NoShape);
+                }
+                let mut distance = 0;
+                while group.iter().all(|c| c.row - distance > 0 &&
+                                           *self.storage.cell(&Coords{row: c.row - distance - 1, col: c.col}) == figures::Shape::NoShape) {
+                    distance += 1;
+                }
+                for (cell, shape) in group.iter().zip(shapes) {
+                    self.storage.set_cell(&Coords{row: cell.row - distance, col: cell.col}, shape);
+                }
+                step_moved |= distance > 0;
+            }
+            /* landed groups may have uncovered others, repeat until nothing moves */
+            if !step_moved {
+                return moved;
+            }
+            moved = true;
+        }
+    }
+
+    /* Clear every completed row at once the way given mode does, cascade
+     * chains included. Returns the number of rows cleared. */
+    pub fn clear_filled(self: &mut Self, clear_mode: ClearMode) -> u32 {
+        let mut lines = 0;
+        loop {
+            /* top down, so deleting doesn't shift the rest */
+            let rows: Vec<i8> = (0..self.storage.dimensions.total_height()).rev().filter(|row| self.row_filled(*row)).collect();
+            if rows.is_empty() {
+                return lines;
+            }
+            lines += rows.len() as u32;
+            for row in rows {
+                match clear_mode {
+                    ClearMode::Naive => self.delete_row(row),
+                    ClearMode::Cascade => self.clear_row(row),
+                }
+            }
+            if clear_mode == ClearMode::Naive || !self.cascade() {
+                return lines;
             }
         }
     }
@@ -228,6 +474,14 @@ impl Playfield {
                       col: coords.col - tetro_coords.col})
     }
 
+    pub fn dimensions(self: &Self) -> Dimensions {
+        self.storage.dimensions
+    }
+
+    pub fn storage(self: &Self) -> &Storage {
+        &self.storage
+    }
+
     pub fn new(storage: Storage) -> Playfield {
         Playfield{storage: storage}
     }
@@ -418,6 +672,115 @@ mod tests {
         assert_eq!(place_result.is_ok(), true);
     }
 
+    #[test]
+    fn turn_i_kicks_off_left_wall() {
+        let playfield: Playfield = Playfield::new(Default::default());
+        let mut active_tetro = FieldTetrimino{
+            tetro: figures::Tetrimino::new(figures::Shape::IShape),
+            coords: Coords{col: 0, row: 10},
+        };
+        /* stand vertically next to the left wall */
+        assert_eq!(playfield.turn_tetro(&mut active_tetro, figures::Rotation::Clockwise), true);
+        assert_eq!(playfield.move_tetro(&mut active_tetro, Dir::Left), true);
+        assert_eq!(playfield.move_tetro(&mut active_tetro, Dir::Left), true);
+        assert_eq!(playfield.move_tetro(&mut active_tetro, Dir::Left), false);
+        /* plain turn is blocked by the wall, so it should be kicked to the right */
+        assert_eq!(playfield.turn_tetro(&mut active_tetro, figures::Rotation::Clockwise), true);
+        assert_eq!(active_tetro.tetro.orientation(), figures::Orientation::Reverse);
+        assert_eq!((active_tetro.coords.col, active_tetro.coords.row), (0, 10));
+    }
+
+    #[test]
+    fn turn_t_kicks_off_floor() {
+        let playfield: Playfield = Playfield::new(Default::default());
+        let mut active_tetro = FieldTetrimino{
+            tetro: figures::Tetrimino::new(figures::Shape::TShape),
+            coords: Coords{col: 0, row: 1},
+        };
+        assert_eq!(playfield.turn_tetro(&mut active_tetro, figures::Rotation::Clockwise), true);
+        assert_eq!(active_tetro.tetro.orientation(), figures::Orientation::Right);
+        assert_eq!((active_tetro.coords.col, active_tetro.coords.row), (-1, 2));
+    }
+
+    #[test]
+    fn turn_ccw_t_kicks_off_floor() {
+        let playfield: Playfield = Playfield::new(Default::default());
+        let mut active_tetro = FieldTetrimino{
+            tetro: figures::Tetrimino::new(figures::Shape::TShape),
+            coords: Coords{col: 0, row: 1},
+        };
+        assert_eq!(playfield.turn_tetro(&mut active_tetro, figures::Rotation::CounterClockwise), true);
+        assert_eq!(active_tetro.tetro.orientation(), figures::Orientation::Left);
+        assert_eq!((active_tetro.coords.col, active_tetro.coords.row), (1, 2));
+    }
+
+    #[test]
+    fn flip_t_kicks_up_from_floor() {
+        let playfield: Playfield = Playfield::new(Default::default());
+        let mut active_tetro = FieldTetrimino{
+            tetro: figures::Tetrimino::new(figures::Shape::TShape),
+            coords: Coords{col: 0, row: 1},
+        };
+        assert_eq!(playfield.turn_tetro(&mut active_tetro, figures::Rotation::Flip), true);
+        assert_eq!(active_tetro.tetro.orientation(), figures::Orientation::Reverse);
+        assert_eq!((active_tetro.coords.col, active_tetro.coords.row), (0, 2));
+    }
+
+    #[test]
+    fn turn_fails_when_all_kicks_blocked() {
+        let mut playfield: Playfield = Playfield::new(Default::default());
+        /* dig a one cell wide well in the third column */
+        for row in (2..=8).step_by(2) {
+            assert_eq!(playfield.place(&figures::Tetrimino::new(figures::Shape::OShape), Coords{col: -1, row: row}).is_ok(), true);
+            assert_eq!(playfield.place(&figures::Tetrimino::new(figures::Shape::OShape), Coords{col: 2, row: row}).is_ok(), true);
+        }
+        let mut tetro = figures::Tetrimino::new(figures::Shape::IShape);
+        figures::rotate(&mut tetro);
+        let mut active_tetro = FieldTetrimino{tetro: tetro, coords: Coords{col: 0, row: 7}};
+        assert_eq!(playfield.turn_tetro(&mut active_tetro, figures::Rotation::Clockwise), false);
+        assert_eq!(active_tetro.tetro.orientation(), figures::Orientation::Right);
+        assert_eq!((active_tetro.coords.col, active_tetro.coords.row), (0, 7));
+    }
+
+    #[test]
+    fn turn_o_never_moves() {
+        let playfield: Playfield = Playfield::new(Default::default());
+        let mut active_tetro = FieldTetrimino{
+            tetro: figures::Tetrimino::new(figures::Shape::OShape),
+            coords: Coords{col: -1, row: 2},
+        };
+        for _ in 0..4 {
+            assert_eq!(playfield.turn_tetro(&mut active_tetro, figures::Rotation::Clockwise), true);
+            assert_eq!((active_tetro.coords.col, active_tetro.coords.row), (-1, 2));
+        }
+    }
+
+    #[test]
+    fn fill_and_delete_row() {
+        let mut playfield: Playfield = Playfield::new(Default::default());
+        /* two rows of o shapes and one l shape on top of them */
+        for col in (-1..WIDTH - 1).step_by(2) {
+            assert_eq!(playfield.place(&figures::Tetrimino::new(figures::Shape::OShape), Coords{col: col, row: 2}).is_ok(), true);
+        }
+        assert_eq!(playfield.place(&figures::Tetrimino::new(figures::Shape::LShape), Coords{col: 0, row: 3}).is_ok(), true);
+        assert_eq!(playfield.row_filled(0), true);
+        assert_eq!(playfield.row_filled(1), true);
+        assert_eq!(playfield.row_filled(2), false);
+
+        playfield.delete_row(0);
+        assert_eq!(playfield.row_filled(0), true);
+        assert_eq!(playfield.row_filled(1), false);
+        playfield.delete_row(0);
+        assert_eq!(playfield.row_filled(0), false);
+        /* l shape has fallen down to the bottom */
+        let empty = FieldTetrimino::default();
+        assert_eq!(playfield.shape_at(&Coords{col: 0, row: 0}, &empty, &empty).shape, figures::Shape::LShape);
+        assert_eq!(playfield.shape_at(&Coords{col: 2, row: 1}, &empty, &empty).shape, figures::Shape::LShape);
+        assert_eq!(playfield.shape_at(&Coords{col: 3, row: 0}, &empty, &empty).shape, figures::Shape::NoShape);
+        assert_eq!(playfield.can_place(&figures::Tetrimino::new(figures::Shape::OShape), &Coords{col: 2, row: 2}), true);
+        assert_eq!(playfield.can_place(&figures::Tetrimino::new(figures::Shape::OShape), &Coords{col: 0, row: 2}), false);
+    }
+
     #[test]
     fn place_failure_outside_bounds() {
         let playfield: Playfield = Playfield::new(Default::default());
@@ -428,4 +791,130 @@ mod tests {
         // doesn't fit horizontally
         assert_eq!(playfield.can_place(&tetro, &Coords{col: WIDTH - 1, row: 5}), false);
     }
+
+    #[test]
+    fn custom_dimensions() {
+        assert_eq!(Dimensions::new(MAX_WIDTH + 1, HEIGHT).is_valid(), false);
+        assert_eq!(Dimensions::new(WIDTH, MIN_HEIGHT - 1).is_valid(), false);
+        assert_eq!(Dimensions::new(MAX_WIDTH, MAX_HEIGHT).is_valid(), true);
+
+        let mut playfield: Playfield = Playfield::new(Storage::new(Dimensions::new(4, 6)));
+        let tetro = figures::Tetrimino::new(figures::Shape::IShape);
+        /* i shape is as wide as playfield */
+        assert_eq!(playfield.can_place(&tetro, &Coords{col: 1, row: 1}), false);
+        assert_eq!(playfield.place(&tetro, Coords{col: 0, row: 1}).is_ok(), true);
+        assert_eq!(playfield.row_filled(0), true);
+        /* buffer rows above visible area are usable too */
+        assert_eq!(playfield.can_place(&tetro, &Coords{col: 0, row: 6 + BUFFER_HEIGHT}), true);
+        assert_eq!(playfield.can_place(&tetro, &Coords{col: 0, row: 6 + BUFFER_HEIGHT + 1}), false);
+
+        let empty = FieldTetrimino::default();
+        assert_eq!(playfield.shape_at(&Coords{col: 3, row: 0}, &empty, &empty).shape, figures::Shape::IShape);
+        assert_eq!(playfield.shape_at(&Coords{col: 4, row: 0}, &empty, &empty).shape, figures::Shape::NoShape);
+        playfield.delete_row(0);
+        assert_eq!(playfield.row_filled(0), false);
+    }
+
+    #[test]
+    fn insert_garbage_pushes_stack_up() {
+        let mut playfield: Playfield = Playfield::new(Default::default());
+        let mut active_tetro = FieldTetrimino::default();
+        assert_eq!(playfield.place(&figures::Tetrimino::new(figures::Shape::OShape), Coords{col: 0, row: 2}).is_ok(), true);
+        assert_eq!(playfield.insert_garbage(2, &[3], &mut active_tetro).is_ok(), true);
+
+        let empty = FieldTetrimino::default();
+        for row in 0..2 {
+            for col in 0..WIDTH {
+                let expected = if col == 3 { figures::Shape::NoShape } else { figures::Shape::Garbage };
+                assert_eq!(playfield.shape_at(&Coords{col: col, row: row}, &empty, &empty).shape, expected);
+            }
+            assert_eq!(playfield.row_filled(row), false);
+        }
+        assert_eq!(playfield.shape_at(&Coords{col: 1, row: 2}, &empty, &empty).shape, figures::Shape::OShape);
+        assert_eq!(playfield.shape_at(&Coords{col: 1, row: 3}, &empty, &empty).shape, figures::Shape::OShape);
+        assert_eq!(playfield.shape_at(&Coords{col: 1, row: 4}, &empty, &empty).shape, figures::Shape::NoShape);
+        /* several holes per row */
+        assert_eq!(playfield.insert_garbage(1, &[0, 9], &mut active_tetro).is_ok(), true);
+        assert_eq!(playfield.shape_at(&Coords{col: 0, row: 0}, &empty, &empty).shape, figures::Shape::NoShape);
+        assert_eq!(playfield.shape_at(&Coords{col: 5, row: 0}, &empty, &empty).shape, figures::Shape::Garbage);
+        assert_eq!(playfield.shape_at(&Coords{col: 9, row: 0}, &empty, &empty).shape, figures::Shape::NoShape);
+    }
+
+    #[test]
+    fn insert_garbage_pushes_active_tetro() {
+        let mut playfield: Playfield = Playfield::new(Default::default());
+        let mut active_tetro = FieldTetrimino{
+            tetro: figures::Tetrimino::new(figures::Shape::OShape),
+            coords: Coords{col: 0, row: 2},
+        };
+        assert_eq!(playfield.insert_garbage(1, &[9], &mut active_tetro).is_ok(), true);
+        assert_eq!(active_tetro.coords.row, 3);
+        assert_eq!(playfield.insert_garbage(1, &[9], &mut active_tetro).is_ok(), true);
+        assert_eq!(active_tetro.coords.row, 4);
+        /* tetro hovering above is left in place */
+        active_tetro.coords.row = 10;
+        assert_eq!(playfield.insert_garbage(1, &[9], &mut active_tetro).is_ok(), true);
+        assert_eq!(active_tetro.coords.row, 10);
+    }
+
+    #[test]
+    fn insert_garbage_top_out() {
+        let mut playfield: Playfield = Playfield::new(Default::default());
+        let mut active_tetro = FieldTetrimino::default();
+        let top = Dimensions::default().total_height() - 1;
+        /* o shape layout starts from its second row */
+        assert_eq!(playfield.place(&figures::Tetrimino::new(figures::Shape::OShape), Coords{col: 0, row: top + 1}).is_ok(), true);
+        assert_eq!(playfield.insert_garbage(1, &[0], &mut active_tetro).is_err(), true);
+        /* nothing has moved */
+        let empty = FieldTetrimino::default();
+        assert_eq!(playfield.shape_at(&Coords{col: 5, row: 0}, &empty, &empty).shape, figures::Shape::NoShape);
+        assert_eq!(playfield.shape_at(&Coords{col: 1, row: top}, &empty, &empty).shape, figures::Shape::OShape);
+    }
+
+    #[test]
+    fn insert_garbage_stuck_active_tetro() {
+        /* column stack reaches two rows below the top, o shape rests on it */
+        let top = Dimensions::default().total_height() - 1;
+        let text = "........GG\n".repeat(top as usize - 1);
+        let mut playfield = Playfield::new(crate::board::Board::parse(&text, &crate::rotation::SRS).unwrap().storage);
+        let before = crate::board::Board::new(playfield.storage().clone()).to_text();
+        let mut active_tetro = FieldTetrimino{
+            tetro: figures::Tetrimino::new(figures::Shape::OShape),
+            coords: Coords{col: 7, row: top + 1},
+        };
+        assert_eq!(playfield.can_place(&active_tetro.tetro, &active_tetro.coords), true);
+        assert_eq!(playfield.insert_garbage(1, &[0], &mut active_tetro).is_err(), true);
+        assert_eq!(crate::board::Board::new(playfield.storage().clone()).to_text(), before);
+        assert_eq!(active_tetro.coords.row, top + 1);
+    }
+
+    fn cascade_board(grid: &str) -> Playfield {
+        Playfield::new(crate::board::Board::parse(&format!("size 4 4\n{}", grid), &crate::rotation::SRS).unwrap().storage)
+    }
+
+    fn grid(playfield: &Playfield) -> String {
+        crate::board::Board::new(playfield.storage().clone()).to_text().replacen("size 4 4\n", "", 1)
+    }
+
+    #[test]
+    fn cascade_drops_groups_separately() {
+        let mut playfield = cascade_board("....\n.OO.\nGGGG\nG...\n");
+        playfield.clear_row(1);
+        assert_eq!(grid(&playfield), "....\n.OO.\n....\nG...\n");
+        assert_eq!(playfield.cascade(), true);
+        assert_eq!(grid(&playfield), "....\n....\n....\nGOO.\n");
+        assert_eq!(playfield.cascade(), false);
+    }
+
+    #[test]
+    fn cascade_keeps_connected_cells_together() {
+        /* garbage block hangs on l shape, the pair falls as a whole past i block */
+        let mut playfield = cascade_board("LLL.\nL.G.\n....\n...I\n");
+        assert_eq!(playfield.cascade(), true);
+        assert_eq!(grid(&playfield), "....\n....\nLLL.\nL.GI\n");
+        /* single o cell on top falls until it lands on t shape */
+        let mut playfield = cascade_board("O...\n....\nTT..\n.T..\n");
+        assert_eq!(playfield.cascade(), true);
+        assert_eq!(grid(&playfield), "....\nO...\nTT..\n.T..\n");
+    }
 }
@@ -1,9 +1,10 @@
 use crate::updateable_view::{UpdatableView, Ctrl};
-use crate::playfield::{Playfield, FieldTetrimino, Dir, HEIGHT};
-use crate::figures::figures::{Shape};
+use crate::playfield::{Playfield, Storage, FieldTetrimino, Dir, ClearMode};
+use crate::figures::figures::{Shape, Rotation};
 use crate::view::{View, ShowArgs};
 use crate::fall::{FRAME_RATE};
 
+#[derive(Clone)]
 pub struct PlayfieldCtrl {
     view: UpdatableView,
     playfield: Playfield,
@@ -11,7 +12,11 @@ pub struct PlayfieldCtrl {
     active_tetro: FieldTetrimino,
     filled_lines: LineStorage,
     animation_frame: u32,
-    is_animating: bool
+    is_animating: bool,
+    clear_delay: u32,
+    clear_mode: ClearMode,
+    /* number of clears caused by last locked tetro */
+    chain: u8,
 }
 
 pub trait Storable {
@@ -20,36 +25,27 @@ pub trait Storable {
     fn reset(self: &mut Self);
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct LineStorage {
-    lines: [i8; 4],
-    write_index: usize,
-    read_index: usize,
+    /* cascade can complete any number of rows at once */
+    lines: Vec<i8>,
 }
 
 impl Storable for LineStorage {
     fn store(self: &mut Self, row: i8) {
-        assert_ne!(self.write_index, self.lines.len());
-        self.lines[self.write_index] = row;
-        self.write_index += 1;
+        self.lines.push(row);
     }
 
-    fn reset(self: &mut Self) { self.write_index = 0; }
+    fn reset(self: &mut Self) { self.lines.clear(); }
     fn elements(self: &Self) -> &[i8] {
-        &self.lines[self.read_index..self.write_index]
-    }
-}
-
-impl Default for LineStorage {
-    fn default() -> LineStorage {
-        LineStorage{lines: [0, 0, 0, 0], write_index: 0, read_index: 0}
+        &self.lines
     }
 }
 
 impl PlayfieldCtrl {
     pub fn move_active(self: &mut Self, dir: Dir) -> (bool, bool) {
         let move_result = self.playfield.move_tetro(&mut self.active_tetro, dir);
-        let fall_space = self.playfield.has_fall_space(&mut self.active_tetro);
+        let fall_space = self.playfield.has_fall_space(&self.active_tetro);
         if move_result {
             self.view.update();
         }
@@ -57,9 +53,9 @@ impl PlayfieldCtrl {
         (move_result, fall_space)
     }
 
-    pub fn turn_active(self: &mut Self) -> (bool, bool) {
-        let move_result = self.playfield.turn_tetro(&mut self.active_tetro);
-        let fall_space = self.playfield.has_fall_space(&mut self.active_tetro);
+    pub fn turn_active(self: &mut Self, rotation: Rotation) -> (bool, bool) {
+        let move_result = self.playfield.turn_tetro(&mut self.active_tetro, rotation);
+        let fall_space = self.playfield.has_fall_space(&self.active_tetro);
         if move_result {
             self.view.update();
         };
@@ -68,11 +64,11 @@ impl PlayfieldCtrl {
     }
 
     pub fn place_active(self: &mut Self) {
-        match self.playfield.place(&self.active_tetro.tetro, self.active_tetro.coords) {
-            Err(_) => panic!("Unable to place active tetro: out of bounds"),
-            _ => {},
+        if self.playfield.place(&self.active_tetro.tetro, self.active_tetro.coords).is_err() {
+            panic!("Unable to place active tetro: out of bounds");
         }
         self.active_tetro.tetro.shape = Shape::NoShape;
+        self.chain = 0;
         self.view.update();
     }
 
@@ -82,40 +78,122 @@ impl PlayfieldCtrl {
         self.playfield.can_place(&self.active_tetro.tetro, &self.active_tetro.coords)
     }
 
+    /* Returns false when garbage tops out the stack */
+    pub fn add_garbage(self: &mut Self, count: i8, holes: &[i8]) -> bool {
+        let result = self.playfield.insert_garbage(count, holes, &mut self.active_tetro);
+        self.view.update();
+        result.is_ok()
+    }
+
+    /* Active tetro has no cells inside of visible area */
+    pub fn active_above_visible(self: &Self) -> bool {
+        self.active_tetro.coords.row - self.active_tetro.tetro.bottom_row() >= self.playfield.dimensions().height
+    }
+
+    pub fn playfield(self: &Self) -> &Playfield {
+        &self.playfield
+    }
+
+    pub fn active(self: &Self) -> &FieldTetrimino {
+        &self.active_tetro
+    }
+
+    /* Where active tetro lands if dropped */
+    pub fn ghost(self: &Self) -> FieldTetrimino {
+        let mut ghost_tetro = self.active_tetro.clone();
+        while self.playfield.move_tetro(&mut ghost_tetro, Dir::Down) {};
+        ghost_tetro
+    }
+
+    /* Continue saved game, returns false if active tetro doesn't fit */
+    pub fn restore(self: &mut Self, active: Option<FieldTetrimino>, chain: u8) -> bool {
+        if let Some(active) = active {
+            if !self.playfield.can_place(&active.tetro, &active.coords) {
+                return false;
+            }
+            self.active_tetro = active;
+        }
+        self.chain = chain;
+        self.view.update();
+        true
+    }
+
     pub fn active_shape(self: &Self) -> Shape {
         self.active_tetro.tetro.shape.clone()
     }
 
     pub fn remove_filled(self: &mut Self) -> usize {
         let removed_lines = self.filled_lines.elements().len();
+        /* lines are stored top down, so deleting doesn't shift the rest */
         for l in self.filled_lines.elements() {
-            self.playfield.delete_row(*l);
+            match self.clear_mode {
+                ClearMode::Naive => self.playfield.delete_row(*l),
+                ClearMode::Cascade => self.playfield.clear_row(*l),
+            }
             self.view.update();
         }
+        if self.clear_mode == ClearMode::Cascade {
+            self.playfield.cascade();
+        }
+        if removed_lines > 0 {
+            self.chain += 1;
+        }
         self.filled_lines.reset();
         removed_lines
     }
 
+    /* Rows remove_filled is going to clear, top down */
+    pub fn filled_rows(self: &Self) -> Vec<i8> {
+        self.filled_lines.elements().to_vec()
+    }
+
+    /* Step of clear chain, cascades may complete new lines after the first clear */
+    pub fn chain(self: &Self) -> u8 {
+        self.chain
+    }
+
     fn find_filled(self: &mut Self) {
-        for r in (0..HEIGHT).rev() {
+        for r in (0..self.playfield.dimensions().total_height()).rev() {
             if self.playfield.row_filled(r) {
                 self.filled_lines.store(r);
             }
         }
     }
 
-    pub fn start_animation(self: &mut Self) {
+    /* Returns true if there are filled lines to animate */
+    pub fn start_animation(self: &mut Self) -> bool {
         self.find_filled();
         self.animation_frame = 0;
-        self.is_animating = self.filled_lines.elements().len() > 0;
+        self.is_animating = !self.filled_lines.elements().is_empty();
+        self.is_animating
+    }
+
+    /* Empty playfield, active tetro is gone as well */
+    pub fn reset_board(self: &mut Self) {
+        self.playfield = Playfield::new(Storage::new(self.playfield.dimensions()));
+        self.active_tetro.tetro.shape = Shape::NoShape;
+        self.filled_lines.reset();
+        self.is_animating = false;
+        self.chain = 0;
+        self.view.update();
     }
 
+    /* Frames completed lines are shown before removal */
+    pub fn set_clear_delay(self: &mut Self, frames: u32) {
+        self.clear_delay = frames;
+    }
+
+    /* Called once per frame, animation lasts one second unless told otherwise */
     pub fn animate(self: &mut Self) -> bool {
+        self.animation_frame += 1;
+        if self.animation_frame >= self.clear_delay {
+            self.is_animating = false;
+        }
         self.view.update();
         self.is_animating
     }
 
-    pub fn new(playfield: Playfield, no_ghost: bool) -> Self {
+    pub fn new(playfield: Playfield, no_ghost: bool, clear_mode: ClearMode) -> Self {
         PlayfieldCtrl{
             playfield: playfield,
             view: UpdatableView::new(true),
@@ -124,6 +202,9 @@ impl PlayfieldCtrl {
             filled_lines: LineStorage::default(),
             animation_frame: 0,
             is_animating: false,
+            clear_delay: FRAME_RATE,
+            clear_mode: clear_mode,
+            chain: 0,
         }
     }
 }
@@ -133,9 +214,7 @@ impl Ctrl for PlayfieldCtrl {
         let ghost_tetro = if self.no_ghost {
             FieldTetrimino::default()
         } else {
-            let mut ghost_tetro = self.active_tetro.clone();
-            while self.playfield.move_tetro(&mut ghost_tetro, Dir::Down) {};
-            ghost_tetro
+            self.ghost()
         };
 
         let selected_lines = if self.is_animating && self.animation_frame % (FRAME_RATE / 2) > (FRAME_RATE / 4) {
@@ -145,10 +224,6 @@ impl Ctrl for PlayfieldCtrl {
             /* select lines in interval between 0..0.25 second */
             self.filled_lines.clone()
         };
-        self.animation_frame += 1;
-        if self.animation_frame == FRAME_RATE {
-            self.is_animating = false;
-        }
 
         self.view.show(view, &ShowArgs::PlayfieldArgs{
                                 playfield: &self.playfield,
@@ -157,6 +232,9 @@ impl Ctrl for PlayfieldCtrl {
                                 selected_lines: &selected_lines,
                              });
     }
+    fn redraw(self: &mut Self) {
+        self.view.update();
+    }
 }
 
 #[cfg(test)]
@@ -197,4 +275,45 @@ mod tests {
         });
         assert!(result.is_err());
     }
+
+    #[test]
+    fn cascade_clears_in_chain() {
+        let board = crate::board::Board::parse("size 4 4\n....\nJJ..\nGGGG\n..GG\n", &crate::rotation::SRS).unwrap();
+        let mut ctrl = PlayfieldCtrl::new(Playfield::new(board.storage), false, ClearMode::Cascade);
+        assert_eq!(ctrl.start_animation(), true);
+        assert_eq!(ctrl.remove_filled(), 1);
+        assert_eq!(ctrl.chain(), 1);
+        /* j shape has fallen into the gap and completed bottom line */
+        assert_eq!(ctrl.start_animation(), true);
+        assert_eq!(ctrl.remove_filled(), 1);
+        assert_eq!(ctrl.chain(), 2);
+        assert_eq!(ctrl.start_animation(), false);
+    }
+
+    #[test]
+    fn cascade_completes_many_rows() {
+        let mut text = "size 4 13\n".to_string();
+        text += &"...I\n".repeat(6);
+        text += "IIII\n";
+        text += &"OOO.\n".repeat(6);
+        let board = crate::board::Board::parse(&text, &crate::rotation::SRS).unwrap();
+        let mut ctrl = PlayfieldCtrl::new(Playfield::new(board.storage), false, ClearMode::Cascade);
+        assert_eq!(ctrl.start_animation(), true);
+        assert_eq!(ctrl.remove_filled(), 1);
+        /* the column above has fallen into all six gaps */
+        assert_eq!(ctrl.start_animation(), true);
+        assert_eq!(ctrl.filled_rows(), vec![5, 4, 3, 2, 1, 0]);
+        assert_eq!(ctrl.remove_filled(), 6);
+        assert_eq!(ctrl.start_animation(), false);
+    }
+
+    #[test]
+    fn naive_clear_has_no_chain() {
+        let board = crate::board::Board::parse("size 4 4\n....\nJJ..\nGGGG\n..GG\n", &crate::rotation::SRS).unwrap();
+        let mut ctrl = PlayfieldCtrl::new(Playfield::new(board.storage), false, ClearMode::Naive);
+        assert_eq!(ctrl.start_animation(), true);
+        assert_eq!(ctrl.remove_filled(), 1);
+        assert_eq!(ctrl.start_animation(), false);
+        assert_eq!(ctrl.chain(), 1);
+    }
 }
@@ -1,15 +1,18 @@
 use crate::updateable_view::{UpdatableView, Ctrl};
 use crate::view::{View, ShowArgs};
 
+#[derive(Clone)]
 pub struct PlaytimeCtrl {
     frame_counter: u32,
+    /* time limited games show time left */
+    limit: Option<u32>,
     view: UpdatableView,
 }
 
 impl PlaytimeCtrl {
     pub fn update(self: &mut Self) {
         self.frame_counter += 1;
-        if self.frame_counter % 7 == 0 {
+        if self.frame_counter.is_multiple_of(7) {
             self.view.update();
         }
     }
@@ -18,9 +21,19 @@ impl PlaytimeCtrl {
         self.frame_counter
     }
 
-    pub fn new() -> Self {
+    pub fn expired(self: &Self) -> bool {
+        self.limit.is_some_and(|limit| self.frame_counter >= limit)
+    }
+
+    pub fn restore(self: &mut Self, frames: u32) {
+        self.frame_counter = frames;
+        self.view.update();
+    }
+
+    pub fn new(limit: Option<u32>) -> Self {
         PlaytimeCtrl {
             frame_counter: 0,
+            limit: limit,
             view: UpdatableView::new(true),
         }
     }
@@ -28,11 +41,16 @@ impl PlaytimeCtrl {
 
 impl Ctrl for PlaytimeCtrl {
     fn show(self: &mut Self, view: &mut impl View) {
-        let min = self.frame_counter / 60 / 60;
-        let sec = self.frame_counter / 60 % 60;
-        let csec = (self.frame_counter % 60) * 100 / 60;
+        let frames = self.limit.map_or(self.frame_counter, |limit| limit.saturating_sub(self.frame_counter));
+        let min = frames / 60 / 60;
+        let sec = frames / 60 % 60;
+        let csec = (frames % 60) * 100 / 60;
+        let countdown = self.limit.is_some();
         self.view.show(view, &ShowArgs::PlaytimeArgs{
-            min, sec, csec
+            min, sec, csec, countdown
         });
     }
+    fn redraw(self: &mut Self) {
+        self.view.update();
+    }
 }
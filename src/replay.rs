@@ -0,0 +1,492 @@
+use crate::engine::engine::{Config, ConfigError, Event, Mode, SPRINT_LINES, CHEESE_ROWS, MAX_LINE_GOAL, MAX_ULTRA_SECONDS};
+use crate::playfield::{Dimensions, ClearMode};
+use crate::piece_set;
+use crate::tlv::tlv;
+use std::io::{Read, Write};
+
+/* Replay file is a sequence of tlv records. Version goes first, then
+ * everything needed to restart the same game (seed, config, rotation
+ * system name, text and digest of a loaded piece set) and user events
+ * stamped with the number of timeouts received before them. Timeouts
+ * themselves are not stored, end record holds the total number of them
+ * instead. */
+const VERSION: u8 = 1;
+
+const TAG_VERSION: tlv::Type = 1;
+const TAG_SEED: tlv::Type = 2;
+const TAG_CONFIG: tlv::Type = 3;
+const TAG_ROTATION: tlv::Type = 4;
+const TAG_EVENT: tlv::Type = 5;
+const TAG_END: tlv::Type = 6;
+/* time or line goal of the mode, if it has one */
+const TAG_GOAL: tlv::Type = 7;
+/* digest of a piece set file, it may change after the game is recorded */
+const TAG_PIECES: tlv::Type = 8;
+/* piece set text, a line per record. Replays recorded before it was
+ * stored name the set by its file path and load the file instead. */
+const TAG_PIECE_LINE: tlv::Type = 9;
+
+/* Position in table is event code in replay file */
+const EVENTS: [Event; 11] = [
+    Event::Timeout, Event::KeyLeft, Event::KeyRight, Event::KeyTurn, Event::KeyTurnCcw,
+    Event::KeyFlip, Event::KeyDown, Event::KeyDrop, Event::KeyHold, Event::KeyExit,
+    Event::KeyPause,
+];
+const CLEAR_MODES: [ClearMode; 2] = [ClearMode::Naive, ClearMode::Cascade];
+
+#[derive(Debug)]
+pub struct ReplayError {
+    pub reason: String,
+}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(e: std::io::Error) -> Self {
+        ReplayError{reason: e.to_string()}
+    }
+}
+
+impl From<ConfigError> for ReplayError {
+    fn from(e: ConfigError) -> Self {
+        ReplayError{reason: e.reason}
+    }
+}
+
+pub struct Replay {
+    pub config: Config,
+    /* frame index and user event */
+    events: Vec<(u32, Event)>,
+    frames: u32,
+}
+
+/* Mode code goes to config record, its goal to a record of its own */
+fn mode_code(mode: &Mode) -> u8 {
+    match mode {
+        Mode::Marathon => 0,
+        Mode::Sprint{..} => 1,
+        Mode::Ultra{..} => 2,
+        Mode::Cheese{..} => 3,
+        Mode::Survival => 4,
+        Mode::Master => 5,
+        Mode::Zen => 6,
+    }
+}
+
+fn mode_goal(mode: &Mode) -> Option<u32> {
+    match mode {
+        Mode::Sprint{lines} => Some(*lines),
+        Mode::Ultra{seconds} => Some(*seconds),
+        Mode::Cheese{rows} => Some(*rows),
+        Mode::Marathon | Mode::Survival | Mode::Master | Mode::Zen => None,
+    }
+}
+
+/* Goals are checked the same way as on the command line, a game
+ * with zero or huge goal would end at once or never */
+fn check_goal(goal: u32, max: u32) -> Result<u32, ReplayError> {
+    if goal == 0 || goal > max {
+        return Err(ReplayError{reason: format!("mode goal {} is out of range 1-{}", goal, max)});
+    }
+    Ok(goal)
+}
+
+fn decode_mode(code: u8, goal: Option<u32>) -> Result<Mode, ReplayError> {
+    match (code, goal) {
+        (0, _) => Ok(Mode::Marathon),
+        /* sprint used to go for 40 lines only */
+        (1, goal) => Ok(Mode::Sprint{lines: check_goal(goal.unwrap_or(SPRINT_LINES), MAX_LINE_GOAL)?}),
+        (2, Some(seconds)) => Ok(Mode::Ultra{seconds: check_goal(seconds, MAX_ULTRA_SECONDS)?}),
+        (3, goal) => Ok(Mode::Cheese{rows: check_goal(goal.unwrap_or(CHEESE_ROWS), MAX_LINE_GOAL)?}),
+        (4, _) => Ok(Mode::Survival),
+        (5, _) => Ok(Mode::Master),
+        (6, _) => Ok(Mode::Zen),
+        _ => Err(ReplayError{reason: format!("unknown mode {} or its goal is missing", code)}),
+    }
+}
+
+pub fn code<T: PartialEq>(table: &[T], item: &T) -> u8 {
+    table.iter().position(|i| i == item).unwrap() as u8
+}
+
+pub fn decode<T: Clone>(table: &[T], code: u8) -> Result<T, ReplayError> {
+    match table.get(code as usize) {
+        Some(item) => Ok(item.clone()),
+        None => Err(ReplayError{reason: format!("unknown code {}", code)}),
+    }
+}
+
+pub fn read_u32(v: &[u8]) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&v[..4]);
+    u32::from_le_bytes(bytes)
+}
+
+pub fn check_length(t: tlv::Type, v: &[u8], length: usize) -> Result<(), ReplayError> {
+    if v.len() != length {
+        return Err(ReplayError{reason: format!("record {} has length {}, expected {}", t, v.len(), length)});
+    }
+    Ok(())
+}
+
+/* Seed, config and rotation system records, saved games store them the same way */
+pub fn write_config(config: &Config, ostream: &mut dyn Write) -> Result<(), ReplayError> {
+    let name = config.rotation_system.name().as_bytes();
+    if name.len() > tlv::Length::MAX as usize {
+        return Err(ReplayError{reason: "rotation system name is too long".to_string()});
+    }
+
+    tlv::encode(TAG_SEED, 8, &config.seed.to_le_bytes(), ostream)?;
+    let dimensions = config.dimensions;
+    tlv::encode(TAG_CONFIG, 8, &[config.no_ghost as u8, config.level, config.next_queue_size,
+                                 mode_code(&config.mode), dimensions.width as u8, dimensions.height as u8,
+                                 dimensions.buffer as u8, code(&CLEAR_MODES, &config.clear_mode)], ostream)?;
+    tlv::encode(TAG_ROTATION, name.len() as tlv::Length, name, ostream)?;
+    if let Some(goal) = mode_goal(&config.mode) {
+        tlv::encode(TAG_GOAL, 4, &goal.to_le_bytes(), ostream)?;
+    }
+    if let Some(digest) = config.rotation_system.digest() {
+        tlv::encode(TAG_PIECES, 8, &digest.to_le_bytes(), ostream)?;
+    }
+    if let Some(text) = config.rotation_system.text() {
+        for line in text.lines() {
+            if line.len() > tlv::Length::MAX as usize {
+                return Err(ReplayError{reason: "piece set line is too long".to_string()});
+            }
+            tlv::encode(TAG_PIECE_LINE, line.len() as tlv::Length, line.as_bytes(), ostream)?;
+        }
+    }
+    Ok(())
+}
+
+/* Collects records written by write_config */
+#[derive(Default)]
+pub struct ConfigReader {
+    seed: Option<u64>,
+    settings: Option<Vec<u8>>,
+    goal: Option<u32>,
+    digest: Option<u64>,
+    rotation: Option<String>,
+    piece_text: Option<String>,
+}
+
+impl ConfigReader {
+    /* Returns false if record is not a part of config */
+    pub fn read(self: &mut Self, t: tlv::Type, v: &[u8]) -> Result<bool, ReplayError> {
+        match t {
+            TAG_SEED => {
+                check_length(t, v, 8)?;
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(v);
+                self.seed = Some(u64::from_le_bytes(bytes));
+            },
+            TAG_CONFIG => {
+                check_length(t, v, 8)?;
+                self.settings = Some(v.to_vec());
+            },
+            TAG_GOAL => {
+                check_length(t, v, 4)?;
+                self.goal = Some(read_u32(v));
+            },
+            TAG_PIECES => {
+                check_length(t, v, 8)?;
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(v);
+                self.digest = Some(u64::from_le_bytes(bytes));
+            },
+            TAG_ROTATION => self.rotation = Some(String::from_utf8_lossy(v).to_string()),
+            TAG_PIECE_LINE => {
+                let text = self.piece_text.get_or_insert_with(String::new);
+                *text += &String::from_utf8_lossy(v);
+                text.push('\n');
+            },
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    pub fn config(self: &Self) -> Result<Config, ReplayError> {
+        let (seed, settings, name) = match (self.seed, &self.settings, &self.rotation) {
+            (Some(seed), Some(settings), Some(name)) => (seed, settings, name),
+            _ => return Err(ReplayError{reason: "incomplete config".to_string()}),
+        };
+        let rotation_system = match &self.piece_text {
+            Some(text) => piece_set::system_from_text(name, text),
+            None => piece_set::system_by_name(name),
+        };
+        let rotation_system = match rotation_system {
+            Ok(system) => system,
+            Err(e) => return Err(ReplayError{reason: format!("cannot load {}: {}", name, e.reason)}),
+        };
+        /* records written before piece set digests were added are not checked */
+        if self.digest.is_some() && self.digest != rotation_system.digest() {
+            return Err(ReplayError{reason: format!("piece set {} has changed since the game was recorded", rotation_system.name())});
+        }
+        let dimensions = Dimensions{width: settings[4] as i8, height: settings[5] as i8, buffer: settings[6] as i8};
+        if !dimensions.is_valid() {
+            return Err(ReplayError{reason: "unsupported playfield size".to_string()});
+        }
+        Ok(Config{
+            no_ghost: settings[0] != 0,
+            level: settings[1],
+            next_queue_size: settings[2],
+            mode: decode_mode(settings[3], self.goal)?,
+            rotation_system: rotation_system,
+            dimensions: dimensions,
+            clear_mode: decode(&CLEAR_MODES, settings[7])?,
+            seed: seed,
+        })
+    }
+}
+
+impl Replay {
+    pub fn new(config: Config) -> Replay {
+        Replay{config: config, events: Vec::new(), frames: 0}
+    }
+
+    /* Remember event given to engine::calculate_frame */
+    pub fn record(self: &mut Self, event: &Event) {
+        if *event == Event::Timeout {
+            self.frames += 1;
+        } else {
+            self.events.push((self.frames, event.clone()));
+        }
+    }
+
+    pub fn frames(self: &Self) -> u32 {
+        self.frames
+    }
+
+    /* Full event stream with timeouts in their places */
+    pub fn events(self: &Self) -> Vec<Event> {
+        let mut result = Vec::new();
+        let mut frame = 0;
+        for (event_frame, event) in self.events.iter() {
+            while frame < *event_frame {
+                result.push(Event::Timeout);
+                frame += 1;
+            }
+            result.push(event.clone());
+        }
+        while frame < self.frames {
+            result.push(Event::Timeout);
+            frame += 1;
+        }
+        result
+    }
+
+    pub fn write(self: &Self, ostream: &mut dyn Write) -> Result<(), ReplayError> {
+        tlv::encode(TAG_VERSION, 1, &[VERSION], ostream)?;
+        write_config(&self.config, ostream)?;
+        for (frame, event) in self.events.iter() {
+            let mut v = frame.to_le_bytes().to_vec();
+            v.push(code(&EVENTS, event));
+            tlv::encode(TAG_EVENT, 5, &v, ostream)?;
+        }
+        tlv::encode(TAG_END, 4, &self.frames.to_le_bytes(), ostream)?;
+        Ok(())
+    }
+
+    pub fn read(istream: &mut dyn Read) -> Result<Replay, ReplayError> {
+        let (t, _, v) = tlv::decode_one(istream)?;
+        if t != TAG_VERSION || v != [VERSION] {
+            return Err(ReplayError{reason: "not a replay or unsupported version".to_string()});
+        }
+
+        let mut config = ConfigReader::default();
+        let mut events = Vec::new();
+        loop {
+            let (t, _, v) = tlv::decode_one(istream)?;
+            if config.read(t, &v)? {
+                continue;
+            }
+            match t {
+                TAG_EVENT => {
+                    check_length(t, &v, 5)?;
+                    events.push((read_u32(&v), decode(&EVENTS, v[4])?));
+                },
+                TAG_END => {
+                    check_length(t, &v, 4)?;
+                    let frames = read_u32(&v);
+                    return Ok(Replay{config: config.config()?, events: events, frames: frames});
+                },
+                /* records from newer versions of the same format are skipped */
+                _ => {},
+            }
+        }
+    }
+
+    pub fn save(self: &Self, path: &str) -> Result<(), ReplayError> {
+        let mut file = std::fs::File::create(path)?;
+        self.write(&mut file)
+    }
+
+    pub fn load(path: &str) -> Result<Replay, ReplayError> {
+        let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+        Replay::read(&mut file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::engine;
+    use crate::rotation::{ARS};
+    use crate::board::{Board};
+
+    fn config() -> Config {
+        Config{no_ghost: true, level: 3, next_queue_size: 2, mode: Mode::Sprint{lines: 20},
+               rotation_system: &ARS, dimensions: Dimensions::new(8, 16), clear_mode: ClearMode::Cascade, seed: 1234}
+    }
+
+    fn played_events() -> Vec<Event> {
+        let mut events = Vec::new();
+        for moves in [[Event::KeyLeft, Event::KeyTurn], [Event::KeyRight, Event::KeyHold], [Event::KeyFlip, Event::KeyTurnCcw]].iter() {
+            events.push(Event::Timeout);
+            events.extend(moves.iter().cloned());
+            events.push(Event::KeyDrop);
+            events.extend(std::iter::repeat_n(Event::Timeout, 60));
+        }
+        events
+    }
+
+    fn board_after(config: Config, events: &[Event]) -> String {
+        let mut game = engine::new_game(config.clone()).unwrap();
+        for event in events {
+            engine::calculate_frame(&mut game, event.clone());
+        }
+        Board::new(engine::playfield(&game).storage().clone()).to_text()
+    }
+
+    #[test]
+    fn events_round_trip() {
+        let mut replay = Replay::new(config());
+        for event in played_events().iter() {
+            replay.record(event);
+        }
+        assert_eq!(replay.frames(), 183);
+        assert_eq!(replay.events(), played_events());
+
+        let mut buf: Vec<u8> = Vec::new();
+        assert_eq!(replay.write(&mut buf).is_ok(), true);
+        let loaded = Replay::read(&mut &buf[..]).unwrap();
+        assert_eq!(loaded.events(), played_events());
+        assert_eq!(loaded.config.seed, 1234);
+        assert_eq!(loaded.config.level, 3);
+        assert_eq!(loaded.config.next_queue_size, 2);
+        assert_eq!(loaded.config.mode, Mode::Sprint{lines: 20});
+        assert_eq!(loaded.config.rotation_system.name(), "ars");
+        assert_eq!(loaded.config.dimensions, Dimensions::new(8, 16));
+        assert_eq!(loaded.config.clear_mode, ClearMode::Cascade);
+    }
+
+    #[test]
+    fn mode_goal_round_trip() {
+        for mode in [Mode::Ultra{seconds: 90}, Mode::Cheese{rows: 7}, Mode::Survival, Mode::Master, Mode::Zen].iter() {
+            let mut buf: Vec<u8> = Vec::new();
+            let config = Config{mode: mode.clone(), ..config()};
+            assert_eq!(Replay::new(config).write(&mut buf).is_ok(), true);
+            assert_eq!(Replay::read(&mut &buf[..]).unwrap().config.mode, *mode);
+        }
+    }
+
+    #[test]
+    fn goal_out_of_range() {
+        for mode in [Mode::Sprint{lines: 0}, Mode::Ultra{seconds: 0}, Mode::Cheese{rows: 0},
+                     Mode::Sprint{lines: MAX_LINE_GOAL + 1}, Mode::Ultra{seconds: MAX_ULTRA_SECONDS + 1}].iter() {
+            let mut buf: Vec<u8> = Vec::new();
+            let config = Config{mode: mode.clone(), ..config()};
+            assert_eq!(Replay::new(config).write(&mut buf).is_ok(), true);
+            assert_eq!(Replay::read(&mut &buf[..]).is_err(), true, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn replay_reproduces_game() {
+        let mut replay = Replay::new(config());
+        for event in played_events().iter() {
+            replay.record(event);
+        }
+        let mut buf: Vec<u8> = Vec::new();
+        assert_eq!(replay.write(&mut buf).is_ok(), true);
+        let loaded = Replay::read(&mut &buf[..]).unwrap();
+        assert_eq!(board_after(loaded.config.clone(), &loaded.events()), board_after(config(), &played_events()));
+    }
+
+    #[test]
+    fn broken_replays() {
+        let mut buf: Vec<u8> = Vec::new();
+        assert_eq!(Replay::new(config()).write(&mut buf).is_ok(), true);
+        /* truncated file */
+        assert_eq!(Replay::read(&mut &buf[..buf.len() - 1]).is_err(), true);
+        /* not a replay at all */
+        assert_eq!(Replay::read(&mut &buf[2..]).is_err(), true);
+        /* unknown rotation system */
+        let mut unknown: Vec<u8> = Vec::new();
+        let mut config = config();
+        config.rotation_system = &crate::rotation::SRS;
+        assert_eq!(Replay::new(config).write(&mut unknown).is_ok(), true);
+        let position = unknown.windows(3).position(|w| w == b"srs").unwrap();
+        unknown[position..position + 3].copy_from_slice(b"xyz");
+        assert_eq!(Replay::read(&mut &unknown[..]).is_err(), true);
+    }
+
+    fn piece_set_replay(dir: &str) -> (String, Vec<u8>) {
+        let dir = std::env::temp_dir().join(format!("tetrust-replay-{}-{}", std::process::id(), dir));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dominoes.pieces").to_str().unwrap().to_string();
+        std::fs::write(&path, "piece D 1 1 1\r\n##\r\n\r\n#\r\n#").unwrap();
+        let mut config = config();
+        config.rotation_system = piece_set::system_by_name(&path).unwrap();
+        let mut replay = Replay::new(config);
+        for event in played_events().iter() {
+            replay.record(event);
+        }
+        let mut buf: Vec<u8> = Vec::new();
+        assert_eq!(replay.write(&mut buf).is_ok(), true);
+        (path, buf)
+    }
+
+    #[test]
+    fn piece_set_travels_with_replay() {
+        let (path, buf) = piece_set_replay("travels");
+        let expected = board_after(Replay::read(&mut &buf[..]).unwrap().config, &played_events());
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_dir(std::path::Path::new(&path).parent().unwrap());
+
+        /* file is gone, replay has everything it needs */
+        let loaded = Replay::read(&mut &buf[..]).unwrap();
+        assert_eq!(loaded.config.rotation_system.name(), "dominoes");
+        assert_eq!(board_after(loaded.config.clone(), &loaded.events()), expected);
+        /* stored set is stored again the same way */
+        let mut again: Vec<u8> = Vec::new();
+        assert_eq!(loaded.write(&mut again).is_ok(), true);
+        assert_eq!(again, buf);
+    }
+
+    #[test]
+    fn piece_set_changed() {
+        let (path, buf) = piece_set_replay("changed");
+        /* replays without piece set text name the file by its path */
+        let mut old: Vec<u8> = Vec::new();
+        let mut istream = &buf[..];
+        while let Ok((t, l, v)) = tlv::decode_one(&mut istream) {
+            match t {
+                TAG_PIECE_LINE => {},
+                TAG_ROTATION => tlv::encode(t, path.len() as tlv::Length, path.as_bytes(), &mut old).unwrap(),
+                _ => tlv::encode(t, l, &v, &mut old).unwrap(),
+            }
+        }
+        assert_eq!(Replay::read(&mut &old[..]).is_ok(), true);
+
+        /* same name, different pieces */
+        std::fs::write(&path, "piece D 1 1 1\n###\n").unwrap();
+        let result = Replay::read(&mut &old[..]);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_dir(std::path::Path::new(&path).parent().unwrap());
+        match result {
+            Err(e) => assert_eq!(e.reason.contains("has changed"), true),
+            Ok(_) => panic!("loaded replay of a changed piece set"),
+        }
+        /* embedded text doesn't depend on the file */
+        assert_eq!(Replay::read(&mut &buf[..]).is_ok(), true);
+    }
+}
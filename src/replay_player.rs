@@ -0,0 +1,257 @@
+use crate::engine::engine::{self, Event, Game};
+use crate::replay::{Replay, ReplayError};
+use crate::view::{View};
+use crate::fall::{FRAME_RATE};
+
+/* Seeking back restores the latest snapshot before target frame
+ * and simulates the rest, so it never replays more than this */
+const SNAPSHOT_INTERVAL: u32 = 10 * FRAME_RATE;
+pub const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 2;
+
+#[derive(Clone)]
+struct Snapshot {
+    frame: u32,
+    index: usize,
+    game: Game,
+}
+
+pub struct ReplayPlayer {
+    events: Vec<Event>,
+    frames: u32,
+    /* next event to feed */
+    index: usize,
+    /* timeouts fed so far */
+    frame: u32,
+    game: Game,
+    snapshots: Vec<Snapshot>,
+    speed: usize,
+    paused: bool,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: &Replay) -> Result<Self, ReplayError> {
+        let config = replay.config.clone();
+        let mut player = ReplayPlayer{
+            events: replay.events(),
+            frames: replay.frames(),
+            index: 0,
+            frame: 0,
+            game: engine::new_game(config)?,
+            snapshots: Vec::new(),
+            speed: NORMAL_SPEED,
+            paused: false,
+        };
+        player.run_frame_events();
+        player.snapshots.push(player.snapshot());
+        Ok(player)
+    }
+
+    fn snapshot(self: &Self) -> Snapshot {
+        Snapshot{frame: self.frame, index: self.index, game: self.game.clone()}
+    }
+
+    fn restore(self: &mut Self, snapshot: Snapshot) {
+        self.frame = snapshot.frame;
+        self.index = snapshot.index;
+        self.game = snapshot.game;
+        engine::redraw(&mut self.game);
+    }
+
+    /* Feed user events recorded between two timeouts */
+    fn run_frame_events(self: &mut Self) {
+        while self.index < self.events.len() && self.events[self.index] != Event::Timeout {
+            engine::calculate_frame(&mut self.game, self.events[self.index].clone());
+            self.index += 1;
+        }
+    }
+
+    /* Advance one frame, returns false at the end of replay */
+    pub fn step(self: &mut Self) -> bool {
+        if self.index >= self.events.len() {
+            return false;
+        }
+        engine::calculate_frame(&mut self.game, Event::Timeout);
+        self.index += 1;
+        self.frame += 1;
+        self.run_frame_events();
+        if self.frame.is_multiple_of(SNAPSHOT_INTERVAL) && self.snapshots.last().unwrap().frame < self.frame {
+            self.snapshots.push(self.snapshot());
+        }
+        true
+    }
+
+    pub fn step_back(self: &mut Self) {
+        if self.frame > 0 {
+            self.seek(self.frame - 1);
+        }
+    }
+
+    pub fn seek(self: &mut Self, frame: u32) {
+        if frame < self.frame {
+            let snapshot = self.snapshots.iter().rev().find(|s| s.frame <= frame).unwrap().clone();
+            self.restore(snapshot);
+        }
+        while self.frame < frame && self.step() {}
+    }
+
+    /* Go to the first frame where condition holds. Condition must stay
+     * true once met. Returns false if replay ends before that. */
+    fn seek_until(self: &mut Self, done: impl Fn(&Game) -> bool) -> bool {
+        if done(&self.game) {
+            let snapshot = self.snapshots.iter().rev().find(|s| !done(&s.game)).unwrap_or(&self.snapshots[0]).clone();
+            self.restore(snapshot);
+        }
+        while !done(&self.game) {
+            if !self.step() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /* Pieces are counted from one */
+    pub fn seek_piece(self: &mut Self, piece: u32) -> bool {
+        self.seek_until(|game| engine::pieces(game) >= piece)
+    }
+
+    pub fn seek_lines(self: &mut Self, lines: u32) -> bool {
+        self.seek_until(|game| engine::lines(game) >= lines)
+    }
+
+    pub fn faster(self: &mut Self) {
+        self.speed = std::cmp::min(self.speed + 1, SPEEDS.len() - 1);
+    }
+
+    pub fn slower(self: &mut Self) {
+        self.speed = self.speed.saturating_sub(1);
+    }
+
+    pub fn speed(self: &Self) -> f32 {
+        SPEEDS[self.speed]
+    }
+
+    pub fn toggle_pause(self: &mut Self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn paused(self: &Self) -> bool {
+        self.paused
+    }
+
+    pub fn frame(self: &Self) -> u32 {
+        self.frame
+    }
+
+    pub fn frames(self: &Self) -> u32 {
+        self.frames
+    }
+
+    pub fn finished(self: &Self) -> bool {
+        self.index >= self.events.len()
+    }
+
+    pub fn game(self: &Self) -> &Game {
+        &self.game
+    }
+
+    pub fn redraw(self: &mut Self) {
+        engine::redraw(&mut self.game);
+    }
+
+    pub fn draw(self: &mut Self, view: &mut impl View) {
+        engine::draw_frame(&mut self.game, view);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::engine::{Config, Mode};
+    use crate::playfield::{Dimensions, ClearMode};
+    use crate::rotation::{SRS};
+    use crate::board::{Board};
+
+    /* Narrow playfield, so pieces dropped in place clear lines now and then */
+    fn recorded() -> Replay {
+        let config = Config{no_ghost: false, level: 0, next_queue_size: 1, mode: Mode::Marathon, rotation_system: &SRS,
+                            dimensions: Dimensions::new(4, 20), clear_mode: ClearMode::Naive, seed: 3};
+        let mut game = engine::new_game(config.clone()).unwrap();
+        let mut replay = Replay::new(config);
+        let moves = [Event::KeyTurn, Event::KeyRight, Event::KeyLeft, Event::KeyDrop];
+        let mut frame = 0;
+        while !engine::is_finished(&game) && frame < 4 * SNAPSHOT_INTERVAL {
+            let event = if frame % 20 == 0 { moves[(frame / 20) as usize % moves.len()].clone() } else { Event::Timeout };
+            replay.record(&event);
+            engine::calculate_frame(&mut game, event);
+            frame += 1;
+        }
+        replay
+    }
+
+    fn board(player: &ReplayPlayer) -> String {
+        Board::new(engine::playfield(player.game()).storage().clone()).to_text()
+    }
+
+    #[test]
+    fn seek_back_matches_playing_forward() {
+        let replay = recorded();
+        let mut forward = ReplayPlayer::new(&replay).unwrap();
+        let mut seeking = ReplayPlayer::new(&replay).unwrap();
+        seeking.seek(replay.frames());
+        assert_eq!(seeking.finished(), true);
+        assert_eq!(seeking.snapshots.len() > 1, true);
+
+        let target = SNAPSHOT_INTERVAL + 123;
+        seeking.seek(target);
+        forward.seek(target);
+        assert_eq!(seeking.frame(), target);
+        assert_eq!(board(&seeking), board(&forward));
+        assert_eq!(engine::pieces(seeking.game()), engine::pieces(forward.game()));
+
+        seeking.step_back();
+        forward = ReplayPlayer::new(&replay).unwrap();
+        forward.seek(target - 1);
+        assert_eq!(seeking.frame(), target - 1);
+        assert_eq!(board(&seeking), board(&forward));
+    }
+
+    #[test]
+    fn seek_piece_and_lines() {
+        let replay = recorded();
+        let mut player = ReplayPlayer::new(&replay).unwrap();
+        player.seek(replay.frames());
+        let lines = engine::lines(player.game());
+        assert_eq!(lines > 1, true);
+
+        assert_eq!(player.seek_lines(lines / 2), true);
+        assert_eq!(engine::lines(player.game()) >= lines / 2, true);
+        let frame = player.frame();
+        player.step_back();
+        assert_eq!(engine::lines(player.game()) < lines / 2, true);
+        /* seeking forward to the same goal ends up on the same frame */
+        assert_eq!(player.seek_lines(lines / 2), true);
+        assert_eq!(player.frame(), frame);
+
+        assert_eq!(player.seek_piece(3), true);
+        assert_eq!(engine::pieces(player.game()), 3);
+        assert_eq!(player.seek_piece(10000), false);
+        assert_eq!(player.finished(), true);
+    }
+
+    #[test]
+    fn speed_limits() {
+        let mut player = ReplayPlayer::new(&recorded()).unwrap();
+        assert_eq!(player.speed(), 1.0);
+        for _ in 0..10 {
+            player.slower();
+        }
+        assert_eq!(player.speed(), 0.25);
+        for _ in 0..10 {
+            player.faster();
+        }
+        assert_eq!(player.speed(), 8.0);
+        player.toggle_pause();
+        assert_eq!(player.paused(), true);
+    }
+}
@@ -0,0 +1,349 @@
+use crate::figures::figures::{Shape, Layout, TetroLayout, Orientation, Rotation, TETROMINOES, widen};
+use crate::playfield::Coords;
+
+pub trait RotationSystem: Sync {
+    fn name(self: &Self) -> &str;
+    /* Layout of the shape in given orientation. Rows go from top to bottom,
+     * so layout row 0 is the highest one. */
+    fn layout(self: &Self, shape: &Shape, orientation: Orientation) -> Layout;
+    /* Offsets to try in order when shape turns from given orientation.
+     * The first test is always turn in place. */
+    fn kicks(self: &Self, shape: &Shape, from: Orientation, rotation: Rotation) -> &'static [Coords];
+    fn spawn_orientation(self: &Self, shape: &Shape) -> Orientation;
+    /* Shapes dealt by the randomizer */
+    fn shapes(self: &Self) -> Vec<Shape> {
+        TETROMINOES.to_vec()
+    }
+    /* Hash of the piece definitions for systems loaded at runtime,
+     * built-in ones can't change between runs */
+    fn digest(self: &Self) -> Option<u64> {
+        None
+    }
+    /* Piece definitions of systems loaded from files, replays and saves
+     * carry them so they don't depend on the file */
+    fn text(self: &Self) -> Option<&str> {
+        None
+    }
+}
+
+/* Super Rotation System used by modern guideline games */
+pub struct Srs;
+/* Arika Rotation System used by TGM series */
+pub struct Arika;
+/* Original Nintendo rotation, right-handed version */
+pub struct Nes;
+
+pub static SRS: Srs = Srs{};
+pub static ARS: Arika = Arika{};
+pub static NES: Nes = Nes{};
+
+pub const KICK_TESTS: usize = 5;
+pub type Kicks = [Coords; KICK_TESTS];
+
+macro_rules! kicks {
+    ($(($col:expr, $row:expr)),*) => {
+        [$(Coords{col: $col, row: $row}),*]
+    };
+}
+
+const O_LAYOUT: TetroLayout = [[0, 0, 0, 0], [0, 1, 1, 0], [0, 1, 1, 0], [0, 0, 0, 0]];
+const NO_LAYOUT: TetroLayout = [[0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]];
+
+/* Layouts for every orientation in order: spawn, right, reverse, left */
+fn srs_layouts(shape: &Shape) -> [TetroLayout; 4] {
+    match shape {
+        /* o shape never changes its layout */
+        Shape::OShape => [O_LAYOUT; 4],
+        Shape::IShape => [[[0, 0, 0, 0], [1, 1, 1, 1], [0, 0, 0, 0], [0, 0, 0, 0]],
+                          [[0, 0, 1, 0], [0, 0, 1, 0], [0, 0, 1, 0], [0, 0, 1, 0]],
+                          [[0, 0, 0, 0], [0, 0, 0, 0], [1, 1, 1, 1], [0, 0, 0, 0]],
+                          [[0, 1, 0, 0], [0, 1, 0, 0], [0, 1, 0, 0], [0, 1, 0, 0]]],
+        Shape::TShape => [[[0, 1, 0, 0], [1, 1, 1, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
+                          [[0, 1, 0, 0], [0, 1, 1, 0], [0, 1, 0, 0], [0, 0, 0, 0]],
+                          [[0, 0, 0, 0], [1, 1, 1, 0], [0, 1, 0, 0], [0, 0, 0, 0]],
+                          [[0, 1, 0, 0], [1, 1, 0, 0], [0, 1, 0, 0], [0, 0, 0, 0]]],
+        Shape::JShape => [[[1, 0, 0, 0], [1, 1, 1, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
+                          [[0, 1, 1, 0], [0, 1, 0, 0], [0, 1, 0, 0], [0, 0, 0, 0]],
+                          [[0, 0, 0, 0], [1, 1, 1, 0], [0, 0, 1, 0], [0, 0, 0, 0]],
+                          [[0, 1, 0, 0], [0, 1, 0, 0], [1, 1, 0, 0], [0, 0, 0, 0]]],
+        Shape::LShape => [[[0, 0, 1, 0], [1, 1, 1, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
+                          [[0, 1, 0, 0], [0, 1, 0, 0], [0, 1, 1, 0], [0, 0, 0, 0]],
+                          [[0, 0, 0, 0], [1, 1, 1, 0], [1, 0, 0, 0], [0, 0, 0, 0]],
+                          [[1, 1, 0, 0], [0, 1, 0, 0], [0, 1, 0, 0], [0, 0, 0, 0]]],
+        Shape::SShape => [[[0, 1, 1, 0], [1, 1, 0, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
+                          [[0, 1, 0, 0], [0, 1, 1, 0], [0, 0, 1, 0], [0, 0, 0, 0]],
+                          [[0, 0, 0, 0], [0, 1, 1, 0], [1, 1, 0, 0], [0, 0, 0, 0]],
+                          [[1, 0, 0, 0], [1, 1, 0, 0], [0, 1, 0, 0], [0, 0, 0, 0]]],
+        Shape::ZShape => [[[1, 1, 0, 0], [0, 1, 1, 0], [0, 0, 0, 0], [0, 0, 0, 0]],
+                          [[0, 0, 1, 0], [0, 1, 1, 0], [0, 1, 0, 0], [0, 0, 0, 0]],
+                          [[0, 0, 0, 0], [1, 1, 0, 0], [0, 1, 1, 0], [0, 0, 0, 0]],
+                          [[0, 1, 0, 0], [1, 1, 0, 0], [1, 0, 0, 0], [0, 0, 0, 0]]],
+        Shape::NoShape | Shape::Garbage | Shape::Custom{..} => [NO_LAYOUT; 4],
+    }
+}
+
+/* SRS wall kick data for J, L, S, T, Z shapes. Offsets are given as (col, row)
+ * with rows growing upwards, the same way as on playfield.
+ * Every table is indexed by orientation the turn starts from. */
+static JLSTZ_KICKS: [Kicks; 4] = [
+    kicks!((0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)), // spawn -> right
+    kicks!((0, 0), (1, 0), (1, -1), (0, 2), (1, 2)),     // right -> reverse
+    kicks!((0, 0), (1, 0), (1, 1), (0, -2), (1, -2)),    // reverse -> left
+    kicks!((0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)),  // left -> spawn
+];
+
+static JLSTZ_CCW_KICKS: [Kicks; 4] = [
+    kicks!((0, 0), (1, 0), (1, 1), (0, -2), (1, -2)),    // spawn -> left
+    kicks!((0, 0), (1, 0), (1, -1), (0, 2), (1, 2)),     // right -> spawn
+    kicks!((0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)), // reverse -> right
+    kicks!((0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)),  // left -> reverse
+];
+
+/* SRS wall kick data for I shape */
+static I_KICKS: [Kicks; 4] = [
+    kicks!((0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)),   // spawn -> right
+    kicks!((0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)),   // right -> reverse
+    kicks!((0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)),   // reverse -> left
+    kicks!((0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)),   // left -> spawn
+];
+
+static I_CCW_KICKS: [Kicks; 4] = [
+    kicks!((0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)),   // spawn -> left
+    kicks!((0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)),   // right -> spawn
+    kicks!((0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)),   // reverse -> right
+    kicks!((0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)),   // left -> reverse
+];
+
+/* SRS doesn't define 180 degree turns, so use the common SRS+ extension
+ * which prefers kicking upwards. Shared by all kickable shapes. */
+static FLIP_KICKS: [[Coords; 6]; 4] = [
+    kicks!((0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)),     // spawn -> reverse
+    kicks!((0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)),       // right -> left
+    kicks!((0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)),  // reverse -> spawn
+    kicks!((0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)),    // left -> right
+];
+
+/* ARS tries to turn in place, then one cell to the right, then to the left */
+static ARS_KICKS: [Coords; 3] = kicks!((0, 0), (1, 0), (-1, 0));
+
+static NO_KICKS: [Coords; 1] = kicks!((0, 0));
+
+impl RotationSystem for Srs {
+    fn name(self: &Self) -> &str { "srs" }
+
+    fn layout(self: &Self, shape: &Shape, orientation: Orientation) -> Layout {
+        widen(&srs_layouts(shape)[orientation as usize])
+    }
+
+    fn kicks(self: &Self, shape: &Shape, from: Orientation, rotation: Rotation) -> &'static [Coords] {
+        match (shape, rotation) {
+            /* o shape can't be kicked, it rotates in place */
+            (Shape::OShape, _) | (Shape::NoShape, _) | (Shape::Garbage, _) | (Shape::Custom{..}, _) => &NO_KICKS,
+            (_, Rotation::Flip) => &FLIP_KICKS[from as usize],
+            (Shape::IShape, Rotation::Clockwise) => &I_KICKS[from as usize],
+            (Shape::IShape, Rotation::CounterClockwise) => &I_CCW_KICKS[from as usize],
+            (_, Rotation::Clockwise) => &JLSTZ_KICKS[from as usize],
+            (_, Rotation::CounterClockwise) => &JLSTZ_CCW_KICKS[from as usize],
+        }
+    }
+
+    fn spawn_orientation(self: &Self, _shape: &Shape) -> Orientation {
+        Orientation::Spawn
+    }
+}
+
+/* ARS pieces are bottom aligned inside 3x3 box, so every horizontal state
+ * rests on the same row. i, s and z shapes have only two states. */
+fn ars_layouts(shape: &Shape) -> [TetroLayout; 4] {
+    match shape {
+        Shape::IShape => {
+            let horizontal = [[0, 0, 0, 0], [1, 1, 1, 1], [0, 0, 0, 0], [0, 0, 0, 0]];
+            let vertical = [[0, 0, 1, 0], [0, 0, 1, 0], [0, 0, 1, 0], [0, 0, 1, 0]];
+            [horizontal, vertical, horizontal, vertical]
+        },
+        Shape::TShape => [[[0, 0, 0, 0], [0, 1, 0, 0], [1, 1, 1, 0], [0, 0, 0, 0]],
+                          [[0, 1, 0, 0], [0, 1, 1, 0], [0, 1, 0, 0], [0, 0, 0, 0]],
+                          [[0, 0, 0, 0], [1, 1, 1, 0], [0, 1, 0, 0], [0, 0, 0, 0]],
+                          [[0, 1, 0, 0], [1, 1, 0, 0], [0, 1, 0, 0], [0, 0, 0, 0]]],
+        Shape::JShape => [[[0, 0, 0, 0], [1, 0, 0, 0], [1, 1, 1, 0], [0, 0, 0, 0]],
+                          [[0, 1, 1, 0], [0, 1, 0, 0], [0, 1, 0, 0], [0, 0, 0, 0]],
+                          [[0, 0, 0, 0], [1, 1, 1, 0], [0, 0, 1, 0], [0, 0, 0, 0]],
+                          [[0, 1, 0, 0], [0, 1, 0, 0], [1, 1, 0, 0], [0, 0, 0, 0]]],
+        Shape::LShape => [[[0, 0, 0, 0], [0, 0, 1, 0], [1, 1, 1, 0], [0, 0, 0, 0]],
+                          [[0, 1, 0, 0], [0, 1, 0, 0], [0, 1, 1, 0], [0, 0, 0, 0]],
+                          [[0, 0, 0, 0], [1, 1, 1, 0], [1, 0, 0, 0], [0, 0, 0, 0]],
+                          [[1, 1, 0, 0], [0, 1, 0, 0], [0, 1, 0, 0], [0, 0, 0, 0]]],
+        Shape::SShape => {
+            let horizontal = [[0, 0, 0, 0], [0, 1, 1, 0], [1, 1, 0, 0], [0, 0, 0, 0]];
+            let vertical = [[1, 0, 0, 0], [1, 1, 0, 0], [0, 1, 0, 0], [0, 0, 0, 0]];
+            [horizontal, vertical, horizontal, vertical]
+        },
+        Shape::ZShape => {
+            let horizontal = [[0, 0, 0, 0], [1, 1, 0, 0], [0, 1, 1, 0], [0, 0, 0, 0]];
+            let vertical = [[0, 0, 1, 0], [0, 1, 1, 0], [0, 1, 0, 0], [0, 0, 0, 0]];
+            [horizontal, vertical, horizontal, vertical]
+        },
+        Shape::OShape => [O_LAYOUT; 4],
+        Shape::NoShape | Shape::Garbage | Shape::Custom{..} => [NO_LAYOUT; 4],
+    }
+}
+
+impl RotationSystem for Arika {
+    fn name(self: &Self) -> &str { "ars" }
+
+    fn layout(self: &Self, shape: &Shape, orientation: Orientation) -> Layout {
+        widen(&ars_layouts(shape)[orientation as usize])
+    }
+
+    /* TGM additionally refuses to kick j, l and t shapes when the center column
+     * blocks the turn. That depends on playfield contents, so it is not modelled here. */
+    fn kicks(self: &Self, shape: &Shape, _from: Orientation, _rotation: Rotation) -> &'static [Coords] {
+        match shape {
+            Shape::OShape | Shape::IShape | Shape::NoShape | Shape::Garbage | Shape::Custom{..} => &NO_KICKS,
+            _ => &ARS_KICKS,
+        }
+    }
+
+    fn spawn_orientation(self: &Self, shape: &Shape) -> Orientation {
+        match shape {
+            /* j, l and t spawn flat side up */
+            Shape::TShape | Shape::JShape | Shape::LShape => Orientation::Reverse,
+            _ => Orientation::Spawn,
+        }
+    }
+}
+
+/* NES turns pieces around their center cell. i, s and z shapes have only
+ * two states with vertical ones leaning to the right. */
+fn nes_layouts(shape: &Shape) -> [TetroLayout; 4] {
+    let srs = srs_layouts(shape);
+    match shape {
+        Shape::IShape | Shape::SShape | Shape::ZShape => {
+            [srs[Orientation::Reverse as usize], srs[Orientation::Right as usize],
+             srs[Orientation::Reverse as usize], srs[Orientation::Right as usize]]
+        },
+        _ => srs,
+    }
+}
+
+impl RotationSystem for Nes {
+    fn name(self: &Self) -> &str { "nes" }
+
+    fn layout(self: &Self, shape: &Shape, orientation: Orientation) -> Layout {
+        widen(&nes_layouts(shape)[orientation as usize])
+    }
+
+    fn kicks(self: &Self, _shape: &Shape, _from: Orientation, _rotation: Rotation) -> &'static [Coords] {
+        &NO_KICKS
+    }
+
+    fn spawn_orientation(self: &Self, shape: &Shape) -> Orientation {
+        match shape {
+            /* j, l and t spawn flat side up */
+            Shape::TShape | Shape::JShape | Shape::LShape => Orientation::Reverse,
+            _ => Orientation::Spawn,
+        }
+    }
+}
+
+/* Look up rotation system by the name used on command line */
+pub fn by_name(name: &str) -> Option<&'static dyn RotationSystem> {
+    match name {
+        "srs" => Some(&SRS),
+        "ars" => Some(&ARS),
+        "nes" => Some(&NES),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROTATIONS: [Rotation; 3] = [Rotation::Clockwise, Rotation::CounterClockwise, Rotation::Flip];
+    const ORIENTATIONS: [Orientation; 4] = [Orientation::Spawn, Orientation::Right, Orientation::Reverse, Orientation::Left];
+
+    fn cells(layout: &Layout) -> usize {
+        layout.iter().map(|row| row.iter().filter(|c| **c != 0).count()).sum()
+    }
+
+    #[test]
+    fn every_layout_has_four_cells() {
+        let systems: [&dyn RotationSystem; 3] = [&SRS, &ARS, &NES];
+        for system in systems.iter() {
+            for shape in [Shape::OShape, Shape::IShape, Shape::TShape, Shape::JShape, Shape::LShape, Shape::SShape, Shape::ZShape].iter() {
+                for orientation in ORIENTATIONS.iter() {
+                    assert_eq!(cells(&system.layout(shape, *orientation)), 4, "{} {:?} {:?}", system.name(), shape, orientation);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn srs_o_shape_has_no_kicks() {
+        for from in ORIENTATIONS.iter() {
+            for rotation in ROTATIONS.iter() {
+                let tests = SRS.kicks(&Shape::OShape, *from, *rotation);
+                assert_eq!(tests.len(), 1);
+                assert_eq!((tests[0].row, tests[0].col), (0, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn kicks_start_with_turn_in_place() {
+        let systems: [&dyn RotationSystem; 3] = [&SRS, &ARS, &NES];
+        for system in systems.iter() {
+            for shape in [Shape::IShape, Shape::TShape, Shape::JShape, Shape::LShape, Shape::SShape, Shape::ZShape].iter() {
+                for rotation in ROTATIONS.iter() {
+                    let tests = system.kicks(shape, Orientation::Spawn, *rotation);
+                    assert_eq!((tests[0].row, tests[0].col), (0, 0), "{} {:?}", system.name(), shape);
+                }
+            }
+        }
+        assert_eq!(SRS.kicks(&Shape::TShape, Orientation::Spawn, Rotation::Clockwise).len(), KICK_TESTS);
+    }
+
+    #[test]
+    fn ars_kicks_right_then_left() {
+        let tests = ARS.kicks(&Shape::TShape, Orientation::Reverse, Rotation::Clockwise);
+        assert_eq!(tests.iter().map(|c| (c.col, c.row)).collect::<Vec<_>>(), vec![(0, 0), (1, 0), (-1, 0)]);
+        assert_eq!(ARS.kicks(&Shape::IShape, Orientation::Spawn, Rotation::Clockwise).len(), 1);
+    }
+
+    #[test]
+    fn nes_never_kicks() {
+        for shape in [Shape::IShape, Shape::TShape, Shape::SShape].iter() {
+            for rotation in ROTATIONS.iter() {
+                assert_eq!(NES.kicks(shape, Orientation::Spawn, *rotation).len(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn flat_side_up_spawn() {
+        assert_eq!(SRS.spawn_orientation(&Shape::TShape), Orientation::Spawn);
+        assert_eq!(ARS.spawn_orientation(&Shape::TShape), Orientation::Reverse);
+        assert_eq!(NES.spawn_orientation(&Shape::JShape), Orientation::Reverse);
+        assert_eq!(NES.spawn_orientation(&Shape::IShape), Orientation::Spawn);
+    }
+
+    #[test]
+    fn two_state_shapes_toggle() {
+        for shape in [Shape::IShape, Shape::SShape, Shape::ZShape].iter() {
+            for system in [&ARS as &dyn RotationSystem, &NES].iter() {
+                assert_eq!(system.layout(shape, Orientation::Spawn), system.layout(shape, Orientation::Reverse));
+                assert_eq!(system.layout(shape, Orientation::Right), system.layout(shape, Orientation::Left));
+                assert_ne!(system.layout(shape, Orientation::Spawn), system.layout(shape, Orientation::Right));
+            }
+        }
+    }
+
+    #[test]
+    fn lookup_by_name() {
+        assert_eq!(by_name("srs").unwrap().name(), "srs");
+        assert_eq!(by_name("ars").unwrap().name(), "ars");
+        assert_eq!(by_name("nes").unwrap().name(), "nes");
+        assert_eq!(by_name("dtet").is_none(), true);
+    }
+}
@@ -1,10 +1,10 @@
 use crate::updateable_view::{UpdatableView, Ctrl};
 use crate::view::{View, ShowArgs};
 use crate::engine::engine::{Mode};
+use crate::master;
 use std::cmp;
 
 const MAX_LEVEL: i8 = 29;
-const SPRINT_LEVEL_GOAL: u32 = 40;
 
 #[derive(Debug)]
 pub enum Score {
@@ -14,9 +14,16 @@ pub enum Score {
         lines_cleared: u32,
         clear_statistic: [u32; 4]
     },
-    Sprint{frames: u32},
+    Sprint{frames: u32, goal: u32},
+    Ultra{score: u32, lines_cleared: u32},
+    Cheese{frames: u32, pieces: u32, goal: u32},
+    /* time survived is what counts */
+    Survival{frames: u32, level: i8, lines_cleared: u32},
+    Master{level: u32, frames: u32, lines_cleared: u32},
+    Zen{score: u32, lines_cleared: u32, clear_statistic: [u32; 4]},
 }
 
+#[derive(Clone)]
 pub struct ScoreCtrl {
     view: UpdatableView,
     level: i8,
@@ -24,6 +31,10 @@ pub struct ScoreCtrl {
     lines_cleared: u32,
     clear_statistic: [u32; 4],
     frames: u32,
+    /* rows with garbage in them, cheese goal counts only these */
+    garbage_cleared: u32,
+    /* master mode level, 0-999, level above is its section */
+    master_level: u32,
     mode: Mode,
 }
 
@@ -48,12 +59,49 @@ impl ScoreCtrl {
     }
 
     pub fn update(self: &mut Self, lines: u8, frames: u32) {
+        self.update_chain(lines, 1, frames);
+    }
+
+    /* Every next step of cascade chain is worth more */
+    pub fn update_chain(self: &mut Self, lines: u8, chain: u8, frames: u32) {
         self.frames = frames;
         if lines > 0 {
             self.lines_cleared += lines as u32;
-            self.level = cmp::max(self.level, (self.lines_cleared / 10) as i8);
-            self.score += ScoreCtrl::score_increment(self.level, lines as u8);
-            self.clear_statistic[(lines - 1) as usize] += 1;
+            if self.mode == Mode::Master {
+                self.set_master_level(master::lines_cleared(self.master_level, lines as u32));
+            }
+            /* zen gravity stays at the chosen level */
+            if self.mode != Mode::Zen {
+                self.level = cmp::max(self.level, (self.lines_cleared / 10) as i8);
+            }
+            self.score += ScoreCtrl::score_increment(self.level, lines) * cmp::max(chain, 1) as u32;
+            /* pentomino clears count as tetrises */
+            self.clear_statistic[cmp::min(lines as usize, self.clear_statistic.len()) - 1] += 1;
+            self.view.update();
+        }
+    }
+
+    fn set_master_level(self: &mut Self, level: u32) {
+        if level != self.master_level {
+            self.master_level = level;
+            self.view.update();
+        }
+    }
+
+    /* Master level goes up with every new piece */
+    pub fn piece_spawned(self: &mut Self) {
+        if self.mode == Mode::Master {
+            self.set_master_level(master::piece_spawned(self.master_level));
+        }
+    }
+
+    pub fn master_level(self: &Self) -> u32 {
+        self.master_level
+    }
+
+    pub fn update_garbage(self: &mut Self, rows: u32) {
+        if rows > 0 {
+            self.garbage_cleared += rows;
             self.view.update();
         }
     }
@@ -61,36 +109,112 @@ impl ScoreCtrl {
     pub fn goal_complete(self: &Self) -> bool {
         match self.mode {
             Mode::Marathon => false,
-            Mode::Sprint => self.lines_cleared >= SPRINT_LEVEL_GOAL,
+            Mode::Sprint{lines} => self.lines_cleared >= lines,
+            /* time limit is watched by playtime */
+            Mode::Ultra{..} => false,
+            Mode::Cheese{rows} => self.garbage_cleared >= rows,
+            Mode::Survival => false,
+            Mode::Master => self.master_level >= master::MAX_LEVEL,
+            Mode::Zen => false,
         }
     }
 
+    pub fn lines(self: &Self) -> u32 {
+        self.lines_cleared
+    }
+
+    pub fn garbage_cleared(self: &Self) -> u32 {
+        self.garbage_cleared
+    }
+
+    /* None for modes without line goal */
+    pub fn lines_left(self: &Self) -> Option<u32> {
+        match self.mode {
+            Mode::Sprint{lines} => Some(lines.saturating_sub(self.lines_cleared)),
+            Mode::Cheese{rows} => Some(rows.saturating_sub(self.garbage_cleared)),
+            _ => None,
+        }
+    }
+
+    /* Master mode levels up with every section */
     pub fn level(self: &Self) -> i8 {
-        self.level
+        match self.mode {
+            Mode::Master => (self.master_level / master::SECTION) as i8,
+            _ => self.level,
+        }
     }
 
-    pub fn score(self: &Self) -> Score {
-        if self.mode == Mode::Marathon {
-            Score::Marathon{
+    pub fn points(self: &Self) -> u32 {
+        self.score
+    }
+
+    pub fn clear_statistic(self: &Self) -> [u32; 4] {
+        self.clear_statistic
+    }
+
+    /* Continue saved game */
+    pub fn restore(self: &mut Self, level: i8, score: u32, lines: u32, clear_statistic: [u32; 4], frames: u32) {
+        self.level = cmp::min(level, MAX_LEVEL);
+        self.score = score;
+        self.lines_cleared = lines;
+        self.clear_statistic = clear_statistic;
+        self.frames = frames;
+        self.view.update();
+    }
+
+    pub fn restore_master_level(self: &mut Self, level: u32) {
+        self.set_master_level(cmp::min(level, master::MAX_LEVEL));
+    }
+
+    /* Pieces are counted by engine, only cheese score shows them */
+    pub fn score(self: &Self, pieces: u32) -> Score {
+        match self.mode {
+            Mode::Marathon => Score::Marathon{
                 level: self.level,
                 score: self.score,
                 lines_cleared: self.lines_cleared,
                 clear_statistic: self.clear_statistic
-            }
-        } else {
-            Score::Sprint{
-                frames: self.frames
-            }
+            },
+            Mode::Sprint{lines} => Score::Sprint{
+                frames: self.frames,
+                goal: lines,
+            },
+            Mode::Ultra{..} => Score::Ultra{
+                score: self.score,
+                lines_cleared: self.lines_cleared,
+            },
+            Mode::Cheese{rows} => Score::Cheese{
+                frames: self.frames,
+                pieces: pieces,
+                goal: rows,
+            },
+            Mode::Survival => Score::Survival{
+                frames: self.frames,
+                level: self.level,
+                lines_cleared: self.lines_cleared,
+            },
+            Mode::Master => Score::Master{
+                level: self.master_level,
+                frames: self.frames,
+                lines_cleared: self.lines_cleared,
+            },
+            Mode::Zen => Score::Zen{
+                score: self.score,
+                lines_cleared: self.lines_cleared,
+                clear_statistic: self.clear_statistic,
+            },
         }
     }
 
     pub fn new(level: i8, mode: Mode) -> Self {
         ScoreCtrl {
             view: UpdatableView::new(true),
-            level: if level < MAX_LEVEL && level >= 0 { level as i8 } else { MAX_LEVEL },
+            level: if (0..MAX_LEVEL).contains(&level) { level } else { MAX_LEVEL },
             score: 0,
             lines_cleared: 0,
             clear_statistic: Default::default(),
+            garbage_cleared: 0,
+            master_level: 0,
             mode: mode,
             frames: 0,
         }
@@ -100,10 +224,55 @@ impl ScoreCtrl {
 impl Ctrl for ScoreCtrl {
     fn show(self: &mut Self, view: &mut impl View) {
         self.view.show(view, &ShowArgs::ScoreArgs{
-            level: self.level,
+            level: match self.mode {
+                Mode::Master => self.master_level,
+                _ => self.level as u32,
+            },
             lines: self.lines_cleared,
+            lines_left: self.lines_left(),
             score: self.score,
             clear_statistic: &self.clear_statistic,
         });
     }
+    fn redraw(self: &mut Self) {
+        self.view.update();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprint_line_goal() {
+        let mut score = ScoreCtrl::new(0, Mode::Sprint{lines: 20});
+        assert_eq!(score.lines_left(), Some(20));
+        for _ in 0..4 {
+            score.update(4, 100);
+        }
+        score.update(3, 150);
+        assert_eq!(score.goal_complete(), false);
+        assert_eq!(score.lines_left(), Some(1));
+        score.update(2, 200);
+        assert_eq!(score.goal_complete(), true);
+        assert_eq!(score.lines_left(), Some(0));
+        assert_eq!(matches!(score.score(0), Score::Sprint{frames: 200, goal: 20}), true);
+        assert_eq!(ScoreCtrl::new(0, Mode::Marathon).lines_left(), None);
+    }
+
+    #[test]
+    fn cheese_counts_garbage_rows() {
+        let mut score = ScoreCtrl::new(0, Mode::Cheese{rows: 5});
+        /* lines made of player pieces only don't bring the goal closer */
+        score.update(4, 100);
+        assert_eq!(score.lines_left(), Some(5));
+        score.update(3, 200);
+        score.update_garbage(3);
+        assert_eq!(score.goal_complete(), false);
+        score.update(2, 300);
+        score.update_garbage(2);
+        assert_eq!(score.goal_complete(), true);
+        assert_eq!(score.lines_left(), Some(0));
+        assert_eq!(matches!(score.score(12), Score::Cheese{frames: 300, pieces: 12, goal: 5}), true);
+    }
 }
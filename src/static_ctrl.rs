@@ -1,6 +1,7 @@
 use crate::updateable_view::{UpdatableView, Ctrl};
 use crate::view::{View, ShowArgs};
 
+#[derive(Clone)]
 pub struct StaticCtrl {
     view: UpdatableView,
     next_queue_size: i8
@@ -10,6 +11,9 @@ impl Ctrl for StaticCtrl {
     fn show(self: &mut Self, view: &mut impl View) {
         self.view.show(view, &ShowArgs::StaticArgs{next_queue_size: self.next_queue_size});
     }
+    fn redraw(self: &mut Self) {
+        self.view.update();
+    }
 }
 
 impl StaticCtrl {
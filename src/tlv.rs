@@ -4,18 +4,18 @@ pub mod tlv {
 
     pub fn encode(t: Type, l: Length, v: &[u8], ostream: &mut dyn std::io::Write) -> std::io::Result<()> {
         let tl: [u8; 2] = [t, l];
-        ostream.write(&tl[..])?;
-        ostream.write(v)?;
+        ostream.write_all(&tl[..])?;
+        ostream.write_all(v)?;
         Ok(())
     }
 
     pub fn decode_one(istream: &mut dyn std::io::Read) -> std::io::Result<(Type, Length, Vec<u8>)> {
         let mut tl: [u8; 2] = [0, 0];
-        istream.read(&mut tl[..])?;
+        istream.read_exact(&mut tl[..])?;
         let t = tl[0];
         let l = tl[1] as usize;
         let mut v = vec![0; l];
-        istream.read(&mut v[0..l])?;
+        istream.read_exact(&mut v[0..l])?;
         Ok((t as Type, l as Length, v))
     }
 }
@@ -32,7 +32,7 @@ mod tests {
     impl std::io::Write for TestIo {
         fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
             self.buf.extend_from_slice(buf);
-            std::io::Result::Ok(0)
+            std::io::Result::Ok(buf.len())
         }
 
         fn flush(&mut self) -> std::io::Result<()> {
@@ -42,10 +42,10 @@ mod tests {
 
     impl std::io::Read for TestIo {
         fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-            if self.buf.len() > 0 {
+            if !self.buf.is_empty() {
                 buf.copy_from_slice(&self.buf[..buf.len()]);
                 self.buf.drain(0..buf.len());
-                std::io::Result::Ok(0)
+                std::io::Result::Ok(buf.len())
             } else {
                 std::io::Result::Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "reading empty"))
             }
@@ -1,5 +1,6 @@
 use crate::view::{View, ShowArgs};
 
+#[derive(Clone)]
 pub struct UpdatableView {
     updated: bool,
 }
@@ -23,4 +24,6 @@ impl UpdatableView {
 
 pub trait Ctrl {
     fn show(self: &mut Self, view: &mut impl View);
+    /* Show everything again on next draw, e.g. after screen was cleared */
+    fn redraw(self: &mut Self);
 }
@@ -1,11 +1,12 @@
-use crate::playfield::{Playfield, FieldTetrimino, WIDTH, HEIGHT, Coords, ShapeAt, ShapeAtType};
+use crate::playfield::{Playfield, FieldTetrimino, Dimensions, Coords, ShapeAt, ShapeAtType};
 use crate::figures::figures::{Shape, Tetrimino, LAYOUT_HEIGHT, LAYOUT_WIDTH};
 use crate::playfield_ctrl::{Storable};
+use crate::rotation::{RotationSystem};
+use crate::engine::engine::{GameOverReason};
 use std::io::{stdout, Write};
 use termion::raw::IntoRawMode;
 extern crate termion;
 
-pub type Row = [char; WIDTH as usize];
 pub enum ShowArgs<'a> {
     StaticArgs{next_queue_size: i8},
     PlayfieldArgs{playfield: &'a Playfield,
@@ -13,10 +14,15 @@ pub enum ShowArgs<'a> {
                   ghost_tetro: &'a FieldTetrimino,
                   selected_lines: &'a dyn Storable,
                  },
-    ScoreArgs{level: i8, score: u32, lines: u32, clear_statistic: &'a [u32; 4]},
-    NextTetroArgs{next: &'a [Shape]},
-    PlaytimeArgs{min: u32, sec: u32, csec: u32},
-    EndgameArgs{game_over: bool},
+    /* line goal modes show lines left instead of lines cleared */
+    ScoreArgs{level: u32, score: u32, lines: u32, lines_left: Option<u32>, clear_statistic: &'a [u32; 4]},
+    NextTetroArgs{next: &'a [Shape], rotation_system: &'static dyn RotationSystem},
+    /* countdown shows time left instead of elapsed */
+    PlaytimeArgs{min: u32, sec: u32, csec: u32, countdown: bool},
+    EndgameArgs{reason: GameOverReason},
+    /* countdown in seconds, none while waiting for the player */
+    PauseArgs{countdown: Option<u32>, next_queue_size: i8},
+    ResetsArgs{resets: u32},
 }
 
 pub trait View {
@@ -24,22 +30,32 @@ pub trait View {
 }
 
 pub struct ConsoleView {
+    dimensions: Dimensions,
+    /* columns right of playfield depend on its width */
+    next_tetro_base_col: i8,
+    score_base_col: u16,
+    park_pos_row: u16,
 }
 const NEXT_TETRO_BASE_ROW: i8 = 4;
-const NEXT_TETRO_BASE_COL: i8 = 26;
+/* distance between playfield border and next queue */
+const NEXT_TETRO_MARGIN: i8 = 4;
 const SCORE_BASE_ROW: u16 = 4;
-const SCORE_BASE_COL: u16 = 40;
+/* next queue width plus a margin */
+const SCORE_MARGIN: u16 = 14;
+/* wide enough for "Tetrises: " followed by a number */
+const SCORE_WIDTH: u16 = 20;
 const PLAYTIME_BASE_ROW: u16 = 13;
-const PLAYTIME_BASE_COL: u16 = 40;
 const PARK_POS_ROW: u16 = 24;
 const PARK_POS_COL: u16 = 1;
+const RESETS_BASE_ROW: u16 = 15;
 const GAMEOVER_BASE_ROW: u16 = 16;
 const GAMEOVER_BASE_COL: u16 = 6;
+const GAMEOVER_HEIGHT: u16 = 6;
 pub const MAX_PREVIEW_SIZE: usize = 4;
 
 macro_rules! rgb_color {
     ($r:expr,$g:expr,$b:expr) => {
-        termion::color::AnsiValue(16 + 36 * $r + 6 * $g + $b);
+        termion::color::AnsiValue(16 + 36 * $r + 6 * $g + $b)
     };
 }
 
@@ -49,14 +65,42 @@ macro_rules! shape_and_color {
     };
 }
 
+impl ConsoleView {
+    pub fn new(dimensions: Dimensions) -> Self {
+        let next_tetro_base_col = dimensions.width * 2 + 2 + NEXT_TETRO_MARGIN;
+        ConsoleView{
+            dimensions: dimensions,
+            next_tetro_base_col: next_tetro_base_col,
+            score_base_col: next_tetro_base_col as u16 + SCORE_MARGIN,
+            park_pos_row: std::cmp::max(PARK_POS_ROW, dimensions.height as u16 + 4),
+        }
+    }
+
+    /* One line below everything else, e.g. for replay position */
+    pub fn show_status(self: &Self, text: &str) {
+        print!("{}{}{}", termion::cursor::Goto(PARK_POS_COL, self.park_pos_row), termion::clear::CurrentLine, text);
+        let mut stdout = stdout().into_raw_mode().unwrap();
+        stdout.flush().unwrap();
+    }
+
+    /* Minimal terminal (width, height) needed to show everything */
+    pub fn required_size(self: &Self) -> (u16, u16) {
+        (self.score_base_col + SCORE_WIDTH, self.park_pos_row + 1)
+    }
+}
+
 impl View for ConsoleView {
     fn show_subview(self: &mut Self, args: &ShowArgs) {
         match args {
-            ShowArgs::ScoreArgs{level, lines, score, clear_statistic} => {
-                show_text_column(&Coords{row: SCORE_BASE_ROW as i8, col: SCORE_BASE_COL as i8}, &[
-                    ("Level: ", &((*level) as u32)),
+            ShowArgs::ScoreArgs{level, lines, lines_left, score, clear_statistic} => {
+                let lines = match lines_left {
+                    Some(left) => ("Lines left: ", left),
+                    None => ("Lines: ", lines),
+                };
+                show_text_column(&Coords{row: SCORE_BASE_ROW as i8, col: self.score_base_col as i8}, &[
+                    ("Level: ", level),
                     ("Score: ", score),
-                    ("Lines: ", lines),
+                    lines,
                     ("Singles: ", &clear_statistic[0]),
                     ("Doubles: ", &clear_statistic[1]),
                     ("Triples: ", &clear_statistic[2]),
@@ -64,45 +108,46 @@ impl View for ConsoleView {
                 ]);
             },
             ShowArgs::StaticArgs{next_queue_size} => {
-                print!("{}Move: ⬅️ ⬇️ ➡️  Rotate: ⬆️  Drop: Spacebar. Hold: h. Exit: q\n\r",
+                print!("{}Move: ⬅️ ⬇️ ➡️  Rotate: ⬆️ /x z a  Drop: Spacebar. Hold: h. Pause: p. Exit: q\n\r",
                        termion::cursor::Goto(1, 1));
-                draw_rectangle(&Coords{row: 2, col: 1}, HEIGHT, WIDTH * 2);
-                draw_rectangle(&Coords{row: NEXT_TETRO_BASE_ROW, col: NEXT_TETRO_BASE_COL}, LAYOUT_HEIGHT * next_queue_size, LAYOUT_WIDTH * 2);
+                draw_rectangle(&Coords{row: 2, col: 1}, self.dimensions.height, self.dimensions.width * 2);
+                draw_rectangle(&Coords{row: NEXT_TETRO_BASE_ROW, col: self.next_tetro_base_col}, LAYOUT_HEIGHT * next_queue_size, LAYOUT_WIDTH * 2);
             },
             ShowArgs::PlayfieldArgs{playfield, active_tetro, ghost_tetro, selected_lines} => {
-                for row in 0..HEIGHT {
+                let Dimensions{width, height, ..} = playfield.dimensions();
+                for row in 0..height {
                     print!("{}", termion::cursor::Goto(2, 3 + (row as u16)));
-                    for col in 0..WIDTH {
-                        let row = HEIGHT - row - 1;
+                    for col in 0..width {
+                        let row = height - row - 1;
                         let color = if selected_lines.elements().contains(&row) {
                             rgb_color!(5, 5, 5)
                         } else {
-                            let shape_at = playfield.shape_at(&Coords{row: row, col: col as i8}, active_tetro, ghost_tetro);
+                            let shape_at = playfield.shape_at(&Coords{row: row, col: col}, active_tetro, ghost_tetro);
                             convert_to_color(shape_at)
                         };
                         print!("{}  {}", termion::color::Bg(color), termion::color::Bg(termion::color::Black));
                     }
                 }
                 print!("{}", termion::color::Bg(termion::color::Black));
-                print!("{}", termion::cursor::Goto(1, HEIGHT as u16 + 4));
+                print!("{}", termion::cursor::Goto(1, height as u16 + 4));
             },
-            ShowArgs::NextTetroArgs{next} => {
+            ShowArgs::NextTetroArgs{next, rotation_system} => {
                 for (index, item) in next.iter().enumerate() {
-                    for (coords, shape) in Tetrimino::new(item.clone()) {
+                    for (coords, shape) in Tetrimino::with_system(item.clone(), *rotation_system) {
                         let color = convert_to_color(ShapeAt{shape: shape, shape_at_type: ShapeAtType::Static});
-                        print!("{}{}  {}", termion::cursor::Goto((NEXT_TETRO_BASE_COL + 1 + coords.col * 2) as u16,
-                                                                 (NEXT_TETRO_BASE_ROW + 1 + coords.row + index as i8 * 4) as u16),
+                        print!("{}{}  {}", termion::cursor::Goto((self.next_tetro_base_col + 1 + coords.col * 2) as u16,
+                                                                 (NEXT_TETRO_BASE_ROW + 1 + coords.row + index as i8 * LAYOUT_HEIGHT) as u16),
                                            termion::color::Bg(color), termion::color::Bg(termion::color::Black));
                     }
                 }
             },
-            ShowArgs::PlaytimeArgs{min, sec, csec} => {
-                print!("{}Time: {:02}:{:02}.{:02}",
-                        termion::cursor::Goto(PLAYTIME_BASE_COL, PLAYTIME_BASE_ROW),
-                        min, sec, csec);
+            ShowArgs::PlaytimeArgs{min, sec, csec, countdown} => {
+                print!("{}{}: {:02}:{:02}.{:02}",
+                        termion::cursor::Goto(self.score_base_col, PLAYTIME_BASE_ROW),
+                        if *countdown { "Time left" } else { "Time" }, min, sec, csec);
             },
-            ShowArgs::EndgameArgs{game_over} => {
-                if *game_over {
+            ShowArgs::EndgameArgs{reason} => {
+                if *reason != GameOverReason::GoalReached && *reason != GameOverReason::TimeUp {
                     show_pixelised(&Coords{row: GAMEOVER_BASE_ROW as i8, col: GAMEOVER_BASE_COL as i8},
                                    &[" ***  *** *   * ****    **  *   * **** *** ",
                                      "*    *  * ** ** *      *  * *   * *    *  *",
@@ -111,9 +156,29 @@ impl View for ConsoleView {
                                      "*  * *  * *   * *      *  *  * *  *    *  *",
                                      " *** *  * *   * ****    **    *   **** *  *"])
                 }
+                print!("{}{}", termion::cursor::Goto(GAMEOVER_BASE_COL, GAMEOVER_BASE_ROW + GAMEOVER_HEIGHT + 1), reason);
+            },
+            ShowArgs::ResetsArgs{resets} => {
+                print!("{}Board resets: {}", termion::cursor::Goto(self.score_base_col, RESETS_BASE_ROW), resets);
+            },
+            ShowArgs::PauseArgs{countdown, next_queue_size} => {
+                /* hide the board, so it can't be studied during pause */
+                let Dimensions{width, height, ..} = self.dimensions;
+                for row in 0..height {
+                    print!("{}{}", termion::cursor::Goto(2, 3 + row as u16), " ".repeat(width as usize * 2));
+                }
+                for row in 0..LAYOUT_HEIGHT * next_queue_size {
+                    print!("{}{}", termion::cursor::Goto(self.next_tetro_base_col as u16 + 1, NEXT_TETRO_BASE_ROW as u16 + 1 + row as u16),
+                                   " ".repeat(LAYOUT_WIDTH as usize * 2));
+                }
+                let text = match countdown {
+                    Some(seconds) => seconds.to_string(),
+                    None => "PAUSED".to_string(),
+                };
+                print!("{}{}", termion::cursor::Goto(2 + (width as u16 * 2 - text.len() as u16) / 2, 3 + height as u16 / 2), text);
             }
         };
-        print!("{}", termion::cursor::Goto(PARK_POS_COL, PARK_POS_ROW));
+        print!("{}", termion::cursor::Goto(PARK_POS_COL, self.park_pos_row));
         let mut stdout = stdout().into_raw_mode().unwrap();
         stdout.flush().unwrap();
     }
@@ -138,9 +203,10 @@ fn draw_rectangle(top_left: &Coords, height: i8, width: i8) {
 
 fn show_text_column(top_left: &Coords, lines: &[(&str, &u32)]) {
     for (index, line) in lines.iter().enumerate() {
-        print!("{}{}{}",
+        /* values may get shorter, e.g. lines left */
+        print!("{}{}{}{}",
                termion::cursor::Goto(top_left.col as u16, (top_left.row + index as i8) as u16),
-               line.0, line.1);
+               line.0, line.1, termion::clear::UntilNewline);
     }
 }
 
@@ -163,7 +229,7 @@ struct ColorTable {
     static_color: termion::color::AnsiValue,
 }
 
-static COLOR_TABLE: &'static [ColorTable] = &[
+static COLOR_TABLE: &[ColorTable] = &[
     shape_and_color!(Shape::OShape, rgb_color!(5, 5, 0), rgb_color!(3, 3, 0)), // yellow
     shape_and_color!(Shape::IShape, rgb_color!(1, 5, 5), rgb_color!(0, 3, 3)), // cyan
     shape_and_color!(Shape::TShape, rgb_color!(5, 0, 5), rgb_color!(2, 0, 2)), // purple
@@ -171,6 +237,7 @@ static COLOR_TABLE: &'static [ColorTable] = &[
     shape_and_color!(Shape::ZShape, rgb_color!(5, 0, 0), rgb_color!(2, 0, 0)), // red
     shape_and_color!(Shape::JShape, rgb_color!(0, 0, 5), rgb_color!(0, 0, 3)), // blue
     shape_and_color!(Shape::LShape, rgb_color!(5, 2, 0), rgb_color!(3, 1, 0)), // orange
+    shape_and_color!(Shape::Garbage, rgb_color!(2, 2, 2), rgb_color!(2, 2, 2)), // grey
 ];
 
 fn convert_to_color(shape_at: ShapeAt) -> termion::color::AnsiValue {
@@ -178,6 +245,15 @@ fn convert_to_color(shape_at: ShapeAt) -> termion::color::AnsiValue {
         return termion::color::AnsiValue::grayscale(3);
     }
 
+    if let Shape::Custom{color: (r, g, b), ..} = shape_at.shape {
+        /* placed pieces are shown dimmer, same as for the table below */
+        return if shape_at.shape_at_type == ShapeAtType::Active {
+            rgb_color!(r, g, b)
+        } else {
+            rgb_color!(r * 3 / 5, g * 3 / 5, b * 3 / 5)
+        };
+    }
+
     for c in COLOR_TABLE {
         if c.shape == shape_at.shape {
             if shape_at.shape_at_type == ShapeAtType::Active {